@@ -0,0 +1,375 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use libfar::conflicts::ConflictScan;
+use libfar::directory::{self, DirectoryOptions};
+use libfar::encoding::NameEncoding;
+use libfar::extract::{ExtractOptions, OnConflict, UnsafeNames};
+use libfar::farlib::{FarError, FarVariant};
+use libfar::reader::FarReader;
+use libfar::verify;
+use libfar::writer::{CompressionPolicy, FarWriter, WriteOptions};
+
+const USAGE: &str = "\
+usage: far <command> [options]
+
+commands:
+    list [-l] [-e <enc>] <archive>              list the files in an archive
+    extract [-o <dir>] [-k|-r] [-s] [-e <enc>] <archive> [names...]
+                                                extract files (all of them by default)
+    create [-v 1|3] [-z] [-d] [-a <bytes>] [-x <glob>] [-e <enc>] <archive> <files...>
+                                                create an archive from files and directories
+    info [-e <enc>] <archive>                   show information about an archive
+    verify [-e <enc>] <archive>                 check an archive for problems
+    conflicts [-i] <archives...>                show files that more than one archive has,
+                                                with later archives overriding earlier ones
+
+options:
+    -l          show sizes and offsets when listing
+    -o <dir>    directory to extract into (default: current directory)
+    -k          keep existing files instead of overwriting them when extracting
+    -r          extract next to existing files under a new name instead of overwriting them
+    -s          extract names that would escape the directory to a sanitized path instead of failing
+    -v 1|3      archive version to create (default: 1)
+    -z          compress files when it makes them smaller
+    -d          store identical files once
+    -a <bytes>  start every file at a multiple of this many bytes (a power of two), padding with zeros
+    -x <glob>   leave out files matching a pattern when adding directories (repeatable)
+    -e <enc>    encoding of file names: utf8 (default), utf8-lossy, cp1252 or raw
+    -i          leave out conflicts where every copy is identical
+
+exit codes:
+    0   success
+    1   i/o error, a requested file wasn't found, or a file has an unsafe name
+    2   invalid usage
+    3   the file is not a valid archive
+    4   the archive can't be written, e.g. a name can't be stored or it is too large";
+
+enum CliError {
+    Usage(String),
+    Archive(PathBuf, FarError),
+    Io(PathBuf, io::Error),
+    Problems(PathBuf, usize),
+    Write(PathBuf, FarError),
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Archive(_, FarError::Io(_) | FarError::NotFound(_) | FarError::UnsafeName(_)) => 1,
+            CliError::Archive(..) => 3,
+            CliError::Io(..) => 1,
+            CliError::Problems(..) => 3,
+            CliError::Write(_, FarError::Io(_)) => 1,
+            CliError::Write(..) => 4,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            CliError::Archive(path, FarError::Io(e)) | CliError::Write(path, FarError::Io(e)) | CliError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            CliError::Archive(path, FarError::NotFound(name)) => write!(f, "{}: no file {}", path.display(), name),
+            CliError::Archive(path, e @ FarError::UnsafeName(_)) => write!(f, "{}: {}", path.display(), e),
+            CliError::Archive(path, e) => write!(f, "{}: not a valid archive: {}", path.display(), e),
+            CliError::Problems(path, count) => write!(f, "{}: found {} problem(s)", path.display(), count),
+            CliError::Write(path, e) => write!(f, "{}: failed to write archive: {}", path.display(), e),
+        }
+    }
+}
+
+type CliResult = Result<(), CliError>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("far: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(args : &[String]) -> CliResult {
+    let (command, args) = args.split_first().ok_or_else(|| CliError::Usage("no command given".to_string()))?;
+    match command.as_str() {
+        "list" => list(args),
+        "extract" => extract(args),
+        "create" => create(args),
+        "info" => info(args),
+        "verify" => verify(args),
+        "conflicts" => conflicts(args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(CliError::Usage(format!("unknown command '{}'", command))),
+    }
+}
+
+/// Command-line arguments split into flags (with their values) and positional arguments.
+struct Args {
+    flags: Vec<(char, Option<String>)>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    /// Parses `args`, where `with_value` lists the flags that take a value.
+    fn parse(args : &[String], flags : &str, with_value : &str) -> Result<Args, CliError> {
+        let mut parsed = Args {
+            flags: Vec::new(),
+            positional: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flag = match arg.strip_prefix('-').map(|flag| flag.chars().collect::<Vec<_>>()) {
+                Some(flag) if flag.len() == 1 => flag[0],
+                _ => {
+                    parsed.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            if !flags.contains(flag) && !with_value.contains(flag) {
+                return Err(CliError::Usage(format!("unknown option '{}'", arg)));
+            }
+            let value = if with_value.contains(flag) {
+                Some(args.next().ok_or_else(|| CliError::Usage(format!("option '{}' needs a value", arg)))?.clone())
+            } else {
+                None
+            };
+            parsed.flags.push((flag, value));
+        }
+        Ok(parsed)
+    }
+
+    fn has(&self, flag : char) -> bool {
+        self.flags.iter().any(|(f, _)| *f == flag)
+    }
+
+    fn value(&self, flag : char) -> Option<&str> {
+        self.values(flag).last()
+    }
+
+    fn values(&self, flag : char) -> impl Iterator<Item = &str> {
+        self.flags.iter().filter(move |(f, _)| *f == flag).filter_map(|(_, value)| value.as_deref())
+    }
+
+    fn encoding(&self) -> Result<NameEncoding, CliError> {
+        match self.value('e').unwrap_or("utf8") {
+            "utf8" => Ok(NameEncoding::Utf8),
+            "utf8-lossy" => Ok(NameEncoding::Utf8Lossy),
+            "cp1252" | "windows-1252" => Ok(NameEncoding::Windows1252),
+            "raw" => Ok(NameEncoding::Raw),
+            encoding => Err(CliError::Usage(format!("unknown name encoding '{}'", encoding))),
+        }
+    }
+
+    /// Splits off the archive path, which is always the first positional argument.
+    fn archive(&self) -> Result<(PathBuf, &[String]), CliError> {
+        let (archive, rest) = self.positional.split_first().ok_or_else(|| CliError::Usage("no archive given".to_string()))?;
+        Ok((PathBuf::from(archive), rest))
+    }
+}
+
+fn open(path : &Path, args : &Args) -> Result<FarReader<BufReader<File>>, CliError> {
+    let file = File::open(path).map_err(|e| CliError::Io(path.to_path_buf(), e))?;
+    FarReader::with_encoding(BufReader::new(file), args.encoding()?).map_err(|e| CliError::Archive(path.to_path_buf(), e))
+}
+
+fn list(args : &[String]) -> CliResult {
+    let args = Args::parse(args, "l", "e")?;
+    let (path, rest) = args.archive()?;
+    if !rest.is_empty() {
+        return Err(CliError::Usage("list takes a single archive".to_string()));
+    }
+    let reader = open(&path, &args)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = reader.files().iter().try_for_each(|info| {
+        if args.has('l') {
+            writeln!(out, "{:>10} {:>10} {:>10}  {}", info.uncompressed_size, info.stored_size, info.offset, info.name)
+        } else {
+            writeln!(out, "{}", info.name)
+        }
+    });
+    result.map_err(|e| CliError::Io(PathBuf::from("<stdout>"), e))
+}
+
+fn extract(args : &[String]) -> CliResult {
+    let args = Args::parse(args, "krs", "oe")?;
+    let (path, names) = args.archive()?;
+    let dest = PathBuf::from(args.value('o').unwrap_or("."));
+    let options = ExtractOptions {
+        on_conflict: if args.has('r') {
+            OnConflict::Rename
+        } else if args.has('k') {
+            OnConflict::Skip
+        } else {
+            OnConflict::Overwrite
+        },
+        unsafe_names: if args.has('s') { UnsafeNames::Sanitize } else { UnsafeNames::Reject },
+    };
+    let mut reader = open(&path, &args)?;
+    let archive_error = |e| CliError::Archive(path.clone(), e);
+    fs::create_dir_all(&dest).map_err(|e| CliError::Io(dest.clone(), e))?;
+    if names.is_empty() {
+        reader.extract_all(&dest, &options).map_err(archive_error)?;
+    } else {
+        for name in names {
+            reader.extract_to(name, &dest, &options).map_err(archive_error)?;
+        }
+    }
+    Ok(())
+}
+
+fn create(args : &[String]) -> CliResult {
+    let args = Args::parse(args, "zd", "vxae")?;
+    let (path, files) = args.archive()?;
+    if files.is_empty() {
+        return Err(CliError::Usage("no files given".to_string()));
+    }
+    let version = match args.value('v').unwrap_or("1") {
+        "1" => 1,
+        "3" => 3,
+        v => return Err(CliError::Usage(format!("unsupported version '{}'", v))),
+    };
+    let options = WriteOptions {
+        compression: if args.has('z') { CompressionPolicy::IfSmaller } else { CompressionPolicy::Never },
+        variant: FarVariant::default(),
+        dedup: args.has('d'),
+        names: args.encoding()?,
+        alignment: match args.value('a') {
+            Some(alignment) => alignment.parse()
+                .ok()
+                .filter(|alignment: &u32| *alignment == 0 || alignment.is_power_of_two())
+                .ok_or_else(|| CliError::Usage(format!("invalid alignment '{}' (must be a power of two)", alignment)))?,
+            None => 0,
+        },
+    };
+    let directory_options = DirectoryOptions {
+        exclude: args.values('x').map(str::to_string).collect(),
+        ..Default::default()
+    };
+    let output = File::create(&path).map_err(|e| CliError::Io(path.clone(), e))?;
+    let result = write_archive(&path, output, version, options, files, &directory_options);
+    if result.is_err() {
+        // don't leave a half written archive behind
+        let _ = fs::remove_file(&path);
+    }
+    result
+}
+
+/// Writes the archive `create` builds to `output`, which was created at `path`.
+fn write_archive(
+    path : &Path,
+    output : File,
+    version : u32,
+    options : WriteOptions,
+    files : &[String],
+    directory_options : &DirectoryOptions,
+) -> CliResult {
+    let write_error = |e| CliError::Write(path.to_path_buf(), e);
+    let mut writer = FarWriter::with_options(BufWriter::new(output), version, options).map_err(write_error)?;
+    // the archive may be inside a directory being added, and must not be added to itself
+    let output_path = fs::canonicalize(path).map_err(|e| CliError::Io(path.to_path_buf(), e))?;
+    for file in files {
+        // directories are stored relative to themselves, with backslashes as the games do, and
+        // single files by their file name
+        let entries = if Path::new(file).is_dir() {
+            directory::walk(file, directory_options).map_err(|e| CliError::Archive(PathBuf::from(file), e))?
+        } else {
+            let name = Path::new(file).file_name().and_then(|name| name.to_str()).ok_or_else(|| {
+                CliError::Io(PathBuf::from(file), io::Error::new(io::ErrorKind::InvalidInput, "not a valid file name"))
+            })?;
+            vec![(name.to_string(), PathBuf::from(file))]
+        };
+        for (name, file) in entries {
+            if fs::canonicalize(&file).is_ok_and(|file| file == output_path) {
+                continue;
+            }
+            let input = File::open(&file).map_err(|e| CliError::Io(file, e))?;
+            writer.add_file(name, BufReader::new(input)).map_err(write_error)?;
+        }
+    }
+    writer.finish().map_err(write_error)?;
+    Ok(())
+}
+
+fn info(args : &[String]) -> CliResult {
+    let args = Args::parse(args, "", "e")?;
+    let (path, rest) = args.archive()?;
+    if !rest.is_empty() {
+        return Err(CliError::Usage("info takes a single archive".to_string()));
+    }
+    let reader = open(&path, &args)?;
+    let files = reader.files();
+    let variant = match (reader.version(), reader.variant()) {
+        (1, FarVariant::V1a) => "a",
+        (1, FarVariant::V1b) => "b",
+        _ => "",
+    };
+    println!("archive:      {}", path.display());
+    println!("version:      {}{}", reader.version(), variant);
+    println!("files:        {}", files.len());
+    println!("compressed:   {}", files.iter().filter(|info| info.is_compressed()).count());
+    println!("total size:   {} bytes", files.iter().map(|info| info.uncompressed_size as u64).sum::<u64>());
+    println!("stored size:  {} bytes", files.iter().map(|info| info.stored_size as u64).sum::<u64>());
+    Ok(())
+}
+
+fn verify(args : &[String]) -> CliResult {
+    let args = Args::parse(args, "", "e")?;
+    let (path, rest) = args.archive()?;
+    if !rest.is_empty() {
+        return Err(CliError::Usage("verify takes a single archive".to_string()));
+    }
+    let names = args.encoding()?;
+    let buffer = fs::read(&path).map_err(|e| CliError::Io(path.clone(), e))?;
+    let report = verify::verify_with(&buffer, names);
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    if !report.is_ok() {
+        return Err(CliError::Problems(path, report.problems.len()));
+    }
+    println!("{}: ok ({} files)", path.display(), report.entries);
+    Ok(())
+}
+
+fn conflicts(args : &[String]) -> CliResult {
+    let args = Args::parse(args, "i", "")?;
+    if args.positional.is_empty() {
+        return Err(CliError::Usage("no archives given".to_string()));
+    }
+    let mut scan = ConflictScan::new();
+    for path in &args.positional {
+        scan.add_archive(path).map_err(|e| CliError::Archive(PathBuf::from(path), e))?;
+    }
+    let report = scan.finish();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = report.conflicts.iter()
+        .filter(|conflict| !(args.has('i') && conflict.is_identical()))
+        .try_for_each(|conflict| {
+            let state = if conflict.is_identical() { "identical" } else { "different" };
+            writeln!(out, "{} ({} copies, {})", conflict.name, conflict.copies.len(), state)?;
+            // the winning copy comes first, marked with a *
+            for (i, copy) in conflict.copies.iter().rev().enumerate() {
+                let marker = if i == 0 { '*' } else { ' ' };
+                // the start of the digest is plenty to tell copies apart by eye
+                let digest: String = copy.sha256[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(out, "  {} {} {:>10}  {}", marker, digest, copy.uncompressed_size,
+                    report.archives[copy.archive].display())?;
+            }
+            Ok(())
+        });
+    result.map_err(|e| CliError::Io(PathBuf::from("<stdout>"), e))
+}
\ No newline at end of file
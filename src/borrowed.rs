@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+
+use crate::encoding::NameEncoding;
+use crate::farlib::{self, FarError, FarV3Info, FarVariant, ManifestEntries, Result};
+
+/// A FAR archive parsed in place, borrowing everything from the archive buffer.
+/// Unlike `farlib::test`, nothing is allocated per entry: names and data are handed out as slices
+/// of the buffer, and the manifest is only walked as entries are requested.
+///
+/// # Examples
+/// ```
+/// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+/// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+/// # ]).to_vec();
+/// // buffer is a Vec<u8> containing the contents of a .far file
+/// use libfar::borrowed::FarArchiveRef;
+/// let archive = FarArchiveRef::parse(&buffer).expect("Not a valid archive");
+/// for entry in archive.entries() {
+///     let entry = entry.expect("Invalid entry");
+///     println!("{} ({} bytes)", entry.name().unwrap_or("<invalid name>"), entry.data.len());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FarArchiveRef<'a> {
+    buf: &'a [u8],
+    version: u32,
+    variant: FarVariant,
+    manifest_offset: u32,
+    len: u32,
+}
+
+/// A single file in a `FarArchiveRef`, borrowed from the archive buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct FarEntryRef<'a> {
+    /// The name as it is stored in the manifest.
+    pub name_bytes: &'a [u8],
+    pub uncompressed_size: u32,
+    pub stored_size: u32,
+    pub offset: u32,
+    pub v3: Option<FarV3Info>,
+    /// The file's bytes as they are stored in the archive.
+    pub data: &'a [u8],
+}
+
+impl<'a> FarArchiveRef<'a> {
+    /// Parses the header and manifest of an archive, without copying anything out of it.
+    /// The manifest is checked to be well formed, but individual entries are only checked when
+    /// they are iterated over.
+    pub fn parse(buf : &'a [u8]) -> Result<FarArchiveRef<'a>> {
+        let (version, manifest_offset) = farlib::parse_header(buf)?;
+        let manifest = buf.get(manifest_offset as usize..)
+            .ok_or(FarError::ManifestOutOfBounds { offset: manifest_offset })?;
+        let variant = farlib::detect_variant(manifest, manifest_offset as u64, version)?;
+        let len = ManifestEntries::new(manifest, manifest_offset as u64, version, variant)?.len();
+        Ok(FarArchiveRef {
+            buf,
+            version,
+            variant,
+            manifest_offset,
+            len,
+        })
+    }
+
+    /// Returns the version of the archive.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the layout of the archive's manifest, only meaningful for version 1 archives.
+    pub fn variant(&self) -> FarVariant {
+        self.variant
+    }
+
+    /// Returns the number of files in the archive.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Returns true if the archive has no files.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the archive's files, in manifest order.
+    pub fn entries(&self) -> Entries<'a> {
+        let manifest = &self.buf[self.manifest_offset as usize..];
+        Entries {
+            inner: ManifestEntries::new(manifest, self.manifest_offset as u64, self.version, self.variant)
+                .expect("manifest was checked when parsing"),
+            buf: self.buf,
+        }
+    }
+
+    /// Returns the same archive over another buffer holding the same bytes, so a parsed
+    /// archive can be kept apart from its buffer without parsing it again.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn with_buf<'b>(self, buf : &'b [u8]) -> FarArchiveRef<'b> {
+        FarArchiveRef {
+            buf,
+            version: self.version,
+            variant: self.variant,
+            manifest_offset: self.manifest_offset,
+            len: self.len,
+        }
+    }
+
+    /// Finds a file by name.
+    pub fn get(&self, name : &str) -> Result<FarEntryRef<'a>> {
+        for entry in self.entries() {
+            let entry = entry?;
+            if entry.name_bytes == name.as_bytes() {
+                return Ok(entry);
+            }
+        }
+        Err(FarError::NotFound(format!("\"{}\"", name)))
+    }
+}
+
+impl<'a> FarEntryRef<'a> {
+    /// Returns the file's name, if it is valid UTF-8.
+    pub fn name(&self) -> Option<&'a str> {
+        std::str::from_utf8(self.name_bytes).ok()
+    }
+
+    /// Returns the file's name decoded with `encoding`, if it is valid in that encoding.
+    pub fn name_with(&self, encoding : NameEncoding) -> Option<Cow<'a, str>> {
+        encoding.decode(self.name_bytes)
+    }
+
+    /// Returns true if the file is stored RefPack-compressed.
+    pub fn is_compressed(&self) -> bool {
+        farlib::is_compressed(self.uncompressed_size, self.stored_size, self.v3)
+    }
+
+    /// Returns the file's contents, borrowed from the archive if the file is stored uncompressed
+    /// and decompressed into a new buffer otherwise.
+    pub fn contents(&self) -> Result<Cow<'a, [u8]>> {
+        if self.is_compressed() {
+            Ok(Cow::Owned(farlib::decompress_entry(self.data, None)?))
+        } else {
+            Ok(Cow::Borrowed(self.data))
+        }
+    }
+}
+
+/// Iterator over the files of a `FarArchiveRef`, returned by `FarArchiveRef::entries`.
+pub struct Entries<'a> {
+    inner: ManifestEntries<'a>,
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<FarEntryRef<'a>>;
+
+    fn next(&mut self) -> Option<Result<FarEntryRef<'a>>> {
+        let index = self.inner.consumed_entries();
+        let entry = match self.inner.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+        let start = entry.offset as usize;
+        let data = start.checked_add(entry.stored_size as usize)
+            .and_then(|end| self.buf.get(start..end))
+            .ok_or(FarError::EntryOutOfBounds {
+                entry_index: Some(index),
+                offset: entry.offset,
+                size: entry.stored_size,
+            });
+        Some(data.map(|data| FarEntryRef {
+            name_bytes: entry.name,
+            uncompressed_size: entry.uncompressed_size,
+            stored_size: entry.stored_size,
+            offset: entry.offset,
+            v3: entry.v3,
+            data,
+        }))
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::farlib::{self, FarFile, Result};
+use crate::index::normalize;
+use crate::sha256::sha256;
+
+/// Collects the entries of a set of archives to find the names that more than one of them has,
+/// such as files that an expansion pack or a mod overrides.
+///
+/// Archives are added in load order, so for each name, the copy in the archive added last wins,
+/// as it does in `FarVfs` with equal priorities. Names are compared ignoring case and slash
+/// direction. If an archive has several entries with the same name, only the first counts.
+///
+/// # Examples
+/// ```no_run
+/// use libfar::conflicts::ConflictScan;
+/// let mut scan = ConflictScan::new();
+/// for path in ["GameData/Objects.far", "ExpansionShared/Objects.far", "Downloads/mod.far"] {
+///     scan.add_archive(path).expect("Failed to read archive");
+/// }
+/// let report = scan.finish();
+/// for conflict in report.conflicts.iter().filter(|conflict| !conflict.is_identical()) {
+///     let winner = conflict.winner();
+///     println!("{} comes from {}", conflict.name, report.archives[winner.archive].display());
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct ConflictScan {
+    archives: Vec<PathBuf>,
+    copies: HashMap<String, Vec<ConflictCopy>>,
+}
+
+/// One archive's copy of a conflicting file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictCopy {
+    /// Position of the archive in `ConflictReport::archives`.
+    pub archive: usize,
+    /// The file's name as spelled in the archive.
+    pub name: String,
+    /// Size of the file's contents, after decompression.
+    pub uncompressed_size: u32,
+    /// Number of bytes the file takes up in the archive.
+    pub stored_size: u32,
+    /// SHA-256 digest of the file's contents after decompression.
+    pub sha256: [u8; 32],
+}
+
+/// A name that more than one archive has.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// The name, normalised as by `index::normalize`.
+    pub name: String,
+    /// Every archive's copy, in the order the archives were added.
+    pub copies: Vec<ConflictCopy>,
+}
+
+impl Conflict {
+    /// Returns the copy that wins, from the archive added last.
+    pub fn winner(&self) -> &ConflictCopy {
+        self.copies.last().expect("a conflict has at least two copies")
+    }
+
+    /// Returns true if every copy has the same contents, so it doesn't matter which one wins.
+    /// Contents are compared by SHA-256 digest, so a file can't be crafted to pass for another.
+    ///
+    /// # Examples
+    /// ```
+    /// use libfar::conflicts::ConflictScan;
+    /// use libfar::farlib::{FarArchive, FarFile};
+    /// let archive = |files : &[(&str, &[u8])]| FarArchive::new_from_files(files.iter()
+    ///     .map(|(name, data)| FarFile::new_from_file(name.to_string(), data.len() as u32, data.to_vec()))
+    ///     .collect()).to_vec();
+    /// let mut scan = ConflictScan::new();
+    /// scan.add("base.far", &archive(&[("Objects\\chair.iff", b"chair"), ("readme.txt", b"hi")]))
+    ///     .expect("Not a valid archive");
+    /// scan.add("mod.far", &archive(&[("objects/CHAIR.iff", b"better chair"), ("readme.txt", b"hi")]))
+    ///     .expect("Not a valid archive");
+    /// let report = scan.finish();
+    /// assert_eq!(report.conflicts.len(), 2);
+    /// let chair = &report.conflicts[0];
+    /// assert_eq!(chair.name, "objects\\chair.iff");
+    /// assert!(!chair.is_identical());
+    /// assert_eq!(chair.winner().name, "objects/CHAIR.iff");
+    /// assert_eq!(chair.winner().archive, 1);
+    /// assert!(report.conflicts[1].is_identical());
+    /// ```
+    pub fn is_identical(&self) -> bool {
+        let first = &self.copies[0];
+        self.copies.iter().all(|copy| copy.uncompressed_size == first.uncompressed_size && copy.sha256 == first.sha256)
+    }
+}
+
+/// The names that more than one archive has, returned by `ConflictScan::finish`.
+#[derive(Debug, Clone)]
+pub struct ConflictReport {
+    /// Paths of the archives that were scanned, in the order they were added.
+    pub archives: Vec<PathBuf>,
+    /// Every name that more than one archive has, sorted by name.
+    pub conflicts: Vec<Conflict>,
+}
+
+impl ConflictScan {
+    /// Creates an empty ConflictScan.
+    pub fn new() -> ConflictScan {
+        ConflictScan::default()
+    }
+
+    /// Reads the archive at `path` and adds its entries.
+    pub fn add_archive(&mut self, path : impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let buffer = fs::read(path)?;
+        self.add(path, &buffer)
+    }
+
+    /// Adds the entries of an archive already in memory. `path` is only used to report where
+    /// each copy comes from.
+    /// Fails without adding anything if the archive can't be parsed or a file can't be
+    /// decompressed.
+    pub fn add(&mut self, path : impl Into<PathBuf>, buffer : &[u8]) -> Result<()> {
+        let archive = farlib::test(buffer)?;
+        let index = self.archives.len();
+        let mut copies = HashMap::new();
+        for info in &archive.file_list {
+            if let Entry::Vacant(entry) = copies.entry(normalize(&info.name)) {
+                let file = FarFile::new_from_entry(info, buffer)?;
+                entry.insert(ConflictCopy {
+                    archive: index,
+                    name: info.name.clone(),
+                    uncompressed_size: file.size,
+                    stored_size: info.stored_size,
+                    sha256: sha256(&file.data),
+                });
+            }
+        }
+        self.archives.push(path.into());
+        for (name, copy) in copies {
+            self.copies.entry(name).or_default().push(copy);
+        }
+        Ok(())
+    }
+
+    /// Returns the names that more than one of the added archives has.
+    pub fn finish(self) -> ConflictReport {
+        let mut conflicts: Vec<Conflict> = self.copies.into_iter()
+            .filter(|(_, copies)| copies.len() > 1)
+            .map(|(name, copies)| Conflict { name, copies })
+            .collect();
+        conflicts.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        ConflictReport {
+            archives: self.archives,
+            conflicts,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::farlib::{FarError, Result};
+
+/// Options for `FarArchive::from_directory` and `directory::walk`.
+///
+/// Patterns are globs matched against paths relative to the directory being packed, using `/`
+/// as the separator and compared case-sensitively:
+/// - `*` matches any run of characters within a path component, and `?` matches one character
+/// - `[abc]`, `[a-z]` and `[!abc]` match one character from (or not from) a set
+/// - `**` as a whole component matches any number of components
+/// - patterns without a `/` match a file or directory with that name at any depth, while
+///   patterns containing a `/` are anchored to the top of the directory
+///
+/// A pattern that matches a directory applies to everything inside it.
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryOptions {
+    /// Only files matching at least one of these patterns are added. Empty means every file.
+    pub include: Vec<String>,
+    /// Files matching any of these patterns are left out, even if they are included.
+    pub exclude: Vec<String>,
+    /// Name of an ignore file (e.g. `".farignore"`) at the top of the directory, holding one
+    /// exclude pattern per line. Blank lines and lines starting with `#` are skipped, and the
+    /// ignore file itself is never added. It's fine for the file not to exist.
+    pub ignore_file: Option<String>,
+}
+
+/// Walks `root` recursively and returns the files that would be packed with `options`, as
+/// pairs of entry name (relative path with backslash separators) and path on disk.
+/// Files are sorted by entry name, so the result doesn't depend on the order the filesystem
+/// lists directories in. Symlinks to files are followed, but symlinks to directories aren't.
+///
+/// Useful for packing a directory with `writer::FarWriter` without loading every file first.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+/// use libfar::directory::{self, DirectoryOptions};
+/// use libfar::writer::FarWriter;
+/// let files = directory::walk("my_mod", &DirectoryOptions::default()).expect("Failed to read directory");
+/// let mut writer = FarWriter::new(File::create("my_mod.far").expect("Failed to create file"))
+///     .expect("Failed to write header");
+/// for (name, path) in files {
+///     writer.add_file(name, File::open(path).expect("Failed to open file")).expect("Failed to add file");
+/// }
+/// writer.finish().expect("Failed to write manifest");
+/// ```
+pub fn walk(root : impl AsRef<Path>, options : &DirectoryOptions) -> Result<Vec<(String, PathBuf)>> {
+    let root = root.as_ref();
+    let mut exclude = options.exclude.clone();
+    if let Some(ignore_file) = &options.ignore_file {
+        exclude.push(format!("/{}", ignore_file));
+        match fs::read_to_string(root.join(ignore_file)) {
+            Ok(contents) => exclude.extend(contents.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, relative)) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                FarError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("file name {:?} is not valid UTF-8", name),
+                ))
+            })?;
+            let path = entry.path();
+            let relative = if relative.is_empty() { name } else { format!("{}/{}", relative, name) };
+            if exclude.iter().any(|pattern| matches_pattern(pattern, &relative)) {
+                continue;
+            }
+            // read_dir doesn't follow symlinks, but fs::metadata does
+            let mut file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                file_type = fs::metadata(&path)?.file_type();
+                if file_type.is_dir() {
+                    continue;
+                }
+            }
+            if file_type.is_dir() {
+                pending.push((path, relative));
+            } else if file_type.is_file() && (options.include.is_empty() || options.include.iter().any(|pattern| matches_path(pattern, &relative))) {
+                files.push((relative.replace('/', "\\"), path));
+            }
+        }
+    }
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(files)
+}
+
+/// Returns true if `pattern` matches `path` (a `/` separated relative path) or any of the
+/// directories it is in. See `DirectoryOptions` for the pattern syntax.
+///
+/// # Examples
+/// ```
+/// use libfar::directory::matches_path;
+/// assert!(matches_path("*.iff", "objects/chair.iff"));
+/// assert!(matches_path("objects/*.iff", "objects/chair.iff"));
+/// assert!(!matches_path("/*.iff", "objects/chair.iff"));
+/// assert!(matches_path("objects", "objects/textures/chair.bmp"));
+/// assert!(matches_path("**/textures/*.bmp", "objects/textures/chair.bmp"));
+/// ```
+pub fn matches_path(pattern : &str, path : &str) -> bool {
+    let mut prefix_len = path.len();
+    loop {
+        if matches_pattern(pattern, &path[..prefix_len]) {
+            return true;
+        }
+        match path[..prefix_len].rfind('/') {
+            Some(i) => prefix_len = i,
+            None => return false,
+        }
+    }
+}
+
+/// Like `matches_path`, but only matches `path` itself.
+fn matches_pattern(pattern : &str, path : &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    let pattern = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if pattern.contains('/') => pattern.to_string(),
+        None => format!("**/{}", pattern),
+    };
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern : &[&str], path : &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                let first: Vec<char> = first.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                match_component(&first, &name) && match_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Matches a single path component against a glob with `*`, `?` and `[...]`.
+fn match_component(pattern : &[char], name : &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some(('[', rest)) => match (name.first(), rest.iter().position(|&c| c == ']')) {
+            (Some(&c), Some(end)) if end > 0 => {
+                let (set, negated) = match rest[0] {
+                    '!' | '^' => (&rest[1..end], true),
+                    _ => (&rest[..end], false),
+                };
+                let mut found = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        found |= (set[i]..=set[i + 2]).contains(&c);
+                        i += 3;
+                    } else {
+                        found |= set[i] == c;
+                        i += 1;
+                    }
+                }
+                found != negated && match_component(&rest[end + 1..], &name[1..])
+            }
+            // an unclosed bracket is matched literally
+            (Some('['), _) => match_component(rest, &name[1..]),
+            _ => false,
+        },
+        Some((&b, rest)) => name.first() == Some(&b) && match_component(rest, &name[1..]),
+    }
+}
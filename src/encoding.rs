@@ -0,0 +1,75 @@
+use std::borrow::Cow;
+
+/// How entry names are converted between the bytes stored in the manifest and `String`s.
+/// Archives from The Sims era store names in the Windows ANSI code page rather than UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameEncoding {
+    /// Names must be valid UTF-8, and archives with other names fail to parse.
+    #[default]
+    Utf8,
+    /// Names are decoded as UTF-8, with invalid sequences replaced by U+FFFD.
+    /// Names are encoded as UTF-8.
+    Utf8Lossy,
+    /// Names are in Windows-1252, the ANSI code page of western versions of Windows.
+    Windows1252,
+    /// Each byte of a name becomes the character with the same value (as in Latin-1), so any
+    /// name can be decoded and encoded again without losing anything.
+    Raw,
+}
+
+/// Characters for bytes 0x80 to 0x9F in Windows-1252, which is Latin-1 everywhere else.
+/// The five unassigned bytes map to the control characters with the same value, as browsers do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl NameEncoding {
+    /// Decodes a name as stored in a manifest, returning `None` if it isn't valid in this
+    /// encoding (which only happens with `Utf8`).
+    ///
+    /// # Examples
+    /// ```
+    /// use libfar::encoding::NameEncoding;
+    /// assert_eq!(NameEncoding::Windows1252.decode(b"caf\xe9 \x80").unwrap(), "café €");
+    /// assert!(NameEncoding::Utf8.decode(b"caf\xe9").is_none());
+    /// ```
+    pub fn decode<'a>(&self, bytes : &'a [u8]) -> Option<Cow<'a, str>> {
+        match self {
+            NameEncoding::Utf8 => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            NameEncoding::Utf8Lossy => Some(String::from_utf8_lossy(bytes)),
+            _ if bytes.is_ascii() => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            NameEncoding::Windows1252 => Some(Cow::Owned(bytes.iter().map(|&b| match b {
+                0x80..=0x9F => WINDOWS_1252_HIGH[b as usize - 0x80],
+                _ => b as char,
+            }).collect())),
+            NameEncoding::Raw => Some(Cow::Owned(bytes.iter().map(|&b| b as char).collect())),
+        }
+    }
+
+    /// Encodes a name to be stored in a manifest, returning `None` if it has characters that
+    /// can't be represented in this encoding.
+    ///
+    /// # Examples
+    /// ```
+    /// use libfar::encoding::NameEncoding;
+    /// assert_eq!(&*NameEncoding::Windows1252.encode("café €").unwrap(), b"caf\xe9 \x80");
+    /// assert!(NameEncoding::Windows1252.encode("日本").is_none());
+    /// ```
+    pub fn encode<'a>(&self, name : &'a str) -> Option<Cow<'a, [u8]>> {
+        match self {
+            NameEncoding::Utf8 | NameEncoding::Utf8Lossy => Some(Cow::Borrowed(name.as_bytes())),
+            _ if name.is_ascii() => Some(Cow::Borrowed(name.as_bytes())),
+            NameEncoding::Windows1252 => name.chars().map(|c| match c as u32 {
+                0..=0x7F | 0xA0..=0xFF => Some(c as u8),
+                _ => WINDOWS_1252_HIGH.iter().position(|&high| high == c).map(|i| 0x80 + i as u8),
+            }).collect::<Option<Vec<u8>>>().map(Cow::Owned),
+            NameEncoding::Raw => name.chars()
+                .map(|c| u8::try_from(c as u32).ok())
+                .collect::<Option<Vec<u8>>>()
+                .map(Cow::Owned),
+        }
+    }
+}
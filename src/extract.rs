@@ -0,0 +1,152 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::farlib::{FarError, Result};
+
+/// What to do when a file being extracted already exists on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Leave the existing file alone and don't extract the entry.
+    Skip,
+    /// Extract the entry next to the existing file, as `name (1).ext`, `name (2).ext` and so on.
+    Rename,
+}
+
+/// What to do with entry names that would escape the destination directory, such as
+/// `..\..\windows\foo` or `/etc/passwd`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsafeNames {
+    /// Fail with `FarError::UnsafeName`.
+    #[default]
+    Reject,
+    /// Drop the offending components (`..`, leading separators and drive prefixes) and extract
+    /// the entry to what remains of its path. Names whose last component is unsafe still fail,
+    /// as there is no file name left to extract to.
+    Sanitize,
+}
+
+/// Options for `FarArchive::extract_all` and `FarReader::extract_all`.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    pub on_conflict: OnConflict,
+    pub unsafe_names: UnsafeNames,
+}
+
+/// Turns an entry name into a path relative to the destination directory, treating both
+/// backslashes and forward slashes as separators.
+/// Returns `FarError::UnsafeName` if the name would escape the destination directory and
+/// `options` doesn't allow sanitizing it, or if its last component isn't a usable file name
+/// (since sanitizing that away would leave the entry named after its directory).
+///
+/// # Examples
+/// ```
+/// use std::path::PathBuf;
+/// use libfar::extract::{self, ExtractOptions, UnsafeNames};
+/// let options = ExtractOptions::default();
+/// let path = extract::entry_path("textures\\wall.bmp", &options).unwrap();
+/// assert_eq!(path, PathBuf::from("textures").join("wall.bmp"));
+/// assert!(extract::entry_path("..\\..\\windows\\foo", &options).is_err());
+///
+/// let options = ExtractOptions { unsafe_names: UnsafeNames::Sanitize, ..Default::default() };
+/// assert_eq!(extract::entry_path("/etc/passwd", &options).unwrap(), PathBuf::from("etc").join("passwd"));
+/// assert!(extract::entry_path("a\\b:c", &options).is_err());
+/// assert!(extract::entry_path("foo\\..", &options).is_err());
+/// ```
+pub fn entry_path(name : &str, options : &ExtractOptions) -> Result<PathBuf> {
+    let mut path = PathBuf::new();
+    let mut safe = !name.starts_with(['\\', '/']);
+    let mut has_file_name = false;
+    for (i, component) in name.split(['\\', '/']).enumerate() {
+        let is_drive = i == 0 && component.len() == 2 && component.ends_with(':');
+        let is_normal = matches!(Path::new(component).components().collect::<Vec<_>>()[..], [Component::Normal(_)]);
+        has_file_name = false;
+        match component {
+            "" | "." => {}
+            _ if is_drive || !is_normal || component.contains(['\0', ':']) => safe = false,
+            _ => {
+                path.push(component);
+                has_file_name = true;
+            }
+        }
+    }
+    if (!safe && options.unsafe_names == UnsafeNames::Reject) || !has_file_name {
+        return Err(FarError::UnsafeName(name.to_string()));
+    }
+    Ok(path)
+}
+
+/// Extracts a single entry called `name` into `dest_dir`, calling `write` to fill in the file.
+/// Returns the path that was written, or `None` if the entry was skipped.
+pub(crate) fn extract_entry(
+    dest_dir : &Path,
+    name : &str,
+    options : &ExtractOptions,
+    write : impl FnOnce(&mut File) -> Result<()>,
+) -> Result<Option<PathBuf>> {
+    let relative = entry_path(name, options)?;
+    // create missing directories one at a time, so we never walk through a symlink
+    let mut target = dest_dir.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        target.push(component);
+        if components.peek().is_none() {
+            break;
+        }
+        match fs::symlink_metadata(&target) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Err(symlink(&target)),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&target)?,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut file = match options.on_conflict {
+        OnConflict::Overwrite => {
+            match fs::symlink_metadata(&target) {
+                Ok(metadata) if metadata.file_type().is_symlink() => return Err(symlink(&target)),
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            File::create(&target)?
+        }
+        OnConflict::Skip => match create_new(&target)? {
+            Some(file) => file,
+            None => return Ok(None),
+        },
+        OnConflict::Rename => {
+            let stem = target.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let extension = target.extension().map(|ext| format!(".{}", ext.to_string_lossy()));
+            let mut n = 1;
+            loop {
+                if let Some(file) = create_new(&target)? {
+                    break file;
+                }
+                target.set_file_name(format!("{} ({}){}", stem, n, extension.as_deref().unwrap_or("")));
+                n += 1;
+            }
+        }
+    };
+    write(&mut file)?;
+    file.flush()?;
+    Ok(Some(target))
+}
+
+/// Creates a file that must not exist yet, returning `None` if it does (symlinks included).
+fn create_new(path : &Path) -> Result<Option<File>> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn symlink(path : &Path) -> FarError {
+    FarError::Io(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("refusing to extract through symlink {}", path.display()),
+    ))
+}
@@ -1,12 +1,311 @@
-use std::io::{BufReader, Read};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::directory::{self, DirectoryOptions};
+use crate::encoding::NameEncoding;
+use crate::extract::{self, ExtractOptions};
+use crate::index::NameIndex;
+use crate::reader::{self, EntryReader};
+use crate::refpack;
+use crate::tree::FarTree;
+use crate::writer::{FarWriter, WriteOptions};
+
+/// Errors that can occur while reading or writing a FAR archive.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FarError {
+    /// The buffer does not start with the `FAR!byAZ` signature.
+    BadMagic,
+    /// The archive ended before a complete header or manifest entry could be read.
+    /// `at_offset` is the position of the field that could not be read, and `entry_index`
+    /// is set if the field belonged to a manifest entry.
+    Truncated { at_offset: u64, entry_index: Option<u32> },
+    /// The manifest offset stored in the header points outside of the archive, or into the
+    /// header itself (as it does in an archive whose writer was never finished).
+    ManifestOutOfBounds { offset: u32 },
+    /// A manifest entry points to data outside of the archive.
+    EntryOutOfBounds { entry_index: Option<u32>, offset: u32, size: u32 },
+    /// A manifest entry's name could not be decoded.
+    InvalidName { entry_index: u32 },
+    /// The requested entry does not exist in the archive.
+    NotFound(String),
+    /// A compressed entry's data is not a valid RefPack stream.
+    InvalidCompressedData { entry_index: Option<u32> },
+    /// The archive uses a version of the format this library does not understand.
+    UnsupportedVersion(u32),
+    /// The archive, or one of its entries, is too large to be described by the manifest.
+    ArchiveTooLarge,
+    /// An entry's name would place it outside of the directory it is being extracted to.
+    UnsafeName(String),
+    /// An entry with this name already exists in the archive.
+    AlreadyExists(String),
+    /// The archive's file data is needed but hasn't been loaded with `load_file_data`.
+    DataNotLoaded,
+    /// The options given to a `FarWriter` can't be used, e.g. an alignment that isn't a power
+    /// of two.
+    InvalidOptions(String),
+    /// An error from the underlying reader or writer.
+    Io(io::Error),
+}
+
+impl fmt::Display for FarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FarError::BadMagic => write!(f, "not a FAR archive (bad magic)"),
+            FarError::Truncated { at_offset, entry_index: Some(i) } => {
+                write!(f, "archive truncated at offset {} while reading entry {}", at_offset, i)
+            }
+            FarError::Truncated { at_offset, entry_index: None } => {
+                write!(f, "archive truncated at offset {}", at_offset)
+            }
+            FarError::ManifestOutOfBounds { offset } => {
+                write!(f, "manifest offset {} is out of bounds", offset)
+            }
+            FarError::EntryOutOfBounds { entry_index, offset, size } => {
+                write!(f, "entry ")?;
+                if let Some(i) = entry_index {
+                    write!(f, "{} ", i)?;
+                }
+                write!(f, "({} bytes at offset {}) is outside of the archive", size, offset)
+            }
+            FarError::InvalidName { entry_index } => write!(f, "entry {} has an invalid name", entry_index),
+            FarError::NotFound(entry) => write!(f, "no entry {} in archive", entry),
+            FarError::InvalidCompressedData { entry_index: Some(i) } => {
+                write!(f, "entry {} has invalid compressed data", i)
+            }
+            FarError::InvalidCompressedData { entry_index: None } => write!(f, "invalid compressed data"),
+            FarError::UnsupportedVersion(version) => write!(f, "unsupported FAR version {}", version),
+            FarError::ArchiveTooLarge => write!(f, "archive exceeds the size limits of the FAR format"),
+            FarError::UnsafeName(name) => write!(f, "entry \"{}\" would be extracted outside of the destination", name),
+            FarError::AlreadyExists(name) => write!(f, "entry \"{}\" already exists in archive", name),
+            FarError::DataNotLoaded => write!(f, "archive file data has not been loaded"),
+            FarError::InvalidOptions(reason) => write!(f, "invalid write options: {}", reason),
+            FarError::Io(e) => write!(f, "i/o error: {}", e),
+        }
+    }
+}
+
+impl Error for FarError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FarError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FarError {
+    fn from(e: io::Error) -> FarError {
+        FarError::Io(e)
+    }
+}
+
+/// Result type used throughout libfar.
+pub type Result<T> = std::result::Result<T, FarError>;
+
+/// Bounds-checked cursor over an archive buffer, used when parsing the header and manifest.
+/// `base` is the position of `buf` within the archive, so errors report absolute offsets.
+struct ByteReader<'a> {
+    buf: &'a [u8],
+    base: u64,
+    pos: usize,
+    entry_index: Option<u32>,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(buf : &'a [u8], base : u64, pos : usize) -> ByteReader<'a> {
+        ByteReader {
+            buf,
+            base,
+            pos,
+            entry_index: None,
+        }
+    }
+
+    fn read_bytes(&mut self, len : usize) -> Result<&'a [u8]> {
+        let bytes = self.pos.checked_add(len)
+            .and_then(|end| self.buf.get(self.pos..end))
+            .ok_or(FarError::Truncated {
+                at_offset: self.base + self.pos as u64,
+                entry_index: self.entry_index,
+            })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u24(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(3)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
 
 /// Struct containing information about a file, without reading the actual data of the file.
 /// This should be used in cases where file information is needed to be retrieved quickly
 /// (e.g. when listing files in an archive).
+#[derive(Debug, Clone)]
 pub struct FarFileInfo {
     pub name: String,
-    pub size: u32,
-    offset: u32,
+    /// Size of the file's contents, after decompression.
+    pub uncompressed_size: u32,
+    /// Number of bytes the file takes up in the archive.
+    /// Differs from `uncompressed_size` if the file is compressed.
+    pub stored_size: u32,
+    /// Position of the file's data in the archive. For files that weren't read from an archive,
+    /// this is where `to_vec` writes them if the archive came from `new_from_files`, and 0 for
+    /// files added with `insert`; edits and write options don't update it.
+    pub offset: u32,
+    /// Extra manifest fields, only present in version 3 (The Sims Online) archives.
+    pub v3: Option<FarV3Info>,
+    /// The name exactly as it is stored in the manifest, for entries read from an archive whose
+    /// stored name isn't the UTF-8 encoding of `name` (e.g. Windows-1252 names, or names with
+    /// invalid bytes replaced by `NameEncoding::Utf8Lossy`). It is written back in place of
+    /// `name`, so names survive a round trip unchanged whatever their encoding; set it to `None`
+    /// after changing `name`. Names stored as UTF-8 leave it as `None`, as `name` already holds
+    /// the exact bytes.
+    pub raw_name: Option<Vec<u8>>,
+}
+
+impl FarFileInfo {
+    /// Returns true if the file is stored RefPack-compressed.
+    pub fn is_compressed(&self) -> bool {
+        is_compressed(self.uncompressed_size, self.stored_size, self.v3)
+    }
+}
+
+pub(crate) fn is_compressed(uncompressed_size : u32, stored_size : u32, v3 : Option<FarV3Info>) -> bool {
+    match v3 {
+        Some(v3) => v3.compressed != 0 || stored_size != uncompressed_size,
+        None => stored_size != uncompressed_size,
+    }
+}
+
+/// Decompresses an entry's stored data if the entry is compressed, returning it as is otherwise.
+/// `entry_index` is only used for error reporting.
+pub(crate) fn decode_entry(info : &FarFileInfo, stored : Vec<u8>, entry_index : Option<u32>) -> Result<Vec<u8>> {
+    if !info.is_compressed() {
+        return Ok(stored);
+    }
+    decompress_entry(&stored, entry_index)
+}
+
+/// Decompresses a compressed entry's stored data.
+pub(crate) fn decompress_entry(stored : &[u8], entry_index : Option<u32>) -> Result<Vec<u8>> {
+    refpack::find_stream(stored)
+        .and_then(|stream| refpack::decompress(stream).ok())
+        .ok_or(FarError::InvalidCompressedData { entry_index })
+}
+
+/// Manifest fields specific to version 3 archives, as used by The Sims Online.
+///
+/// # Examples
+/// ```
+/// use libfar::farlib::{self, FarV3Info};
+/// let data = b"FAR!byAZ\x03\0\0\0\x15\0\0\0hello\x01\0\0\0";
+/// // decompressed size, u24 compressed size, data type, offset, compression flag, access number,
+/// // u16 name length, type id, file id, name
+/// let entry = b"\x05\0\0\0\x05\0\0\0\x10\0\0\0\0\x07\x05\0\x34\x12\0\0\x78\x56\0\0a.txt";
+/// let buffer = [&data[..], entry].concat();
+/// let archive = farlib::test(&buffer).expect("Not a valid archive");
+/// assert_eq!(archive.version, 3);
+/// let info = &archive.file_list[0];
+/// assert_eq!((info.name.as_str(), info.uncompressed_size, info.stored_size, info.offset), ("a.txt", 5, 5, 16));
+/// assert_eq!(info.v3, Some(FarV3Info { data_type: 0, compressed: 0, access_number: 7, type_id: 0x1234, file_id: 0x5678 }));
+/// let archive = archive.load_file_data(&buffer).expect("Failed to load files");
+/// assert_eq!(archive.file_data[0].data, b"hello");
+/// // the version 3 fields are written back unchanged
+/// assert_eq!(archive.to_vec(), buffer);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FarV3Info {
+    /// 0x80 if the data is compressed, 0x00 otherwise.
+    pub data_type: u8,
+    pub compressed: u8,
+    pub access_number: u8,
+    pub type_id: u32,
+    pub file_id: u32,
+}
+
+/// Identifies an entry in an archive, either by its name or by its position in the manifest.
+///
+/// Usually created implicitly, as `&str`, `&String` and `usize` all convert into an EntryId.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryId<'a> {
+    Name(&'a str),
+    Index(usize),
+}
+
+impl<'a> From<&'a str> for EntryId<'a> {
+    fn from(name: &'a str) -> EntryId<'a> {
+        EntryId::Name(name)
+    }
+}
+
+impl<'a> From<&'a String> for EntryId<'a> {
+    fn from(name: &'a String) -> EntryId<'a> {
+        EntryId::Name(name)
+    }
+}
+
+impl From<usize> for EntryId<'_> {
+    fn from(index: usize) -> Self {
+        EntryId::Index(index)
+    }
+}
+
+impl fmt::Display for EntryId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryId::Name(name) => write!(f, "\"{}\"", name),
+            EntryId::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+/// Finds entries whose data is shared with an earlier entry (the same offset and stored size),
+/// as written when deduplicating, returning pairs of (entry, earlier entry) indices.
+/// Entries without any data are never counted as aliases.
+pub(crate) fn find_aliases(ranges : impl IntoIterator<Item = (u32, u32)>) -> Vec<(usize, usize)> {
+    let mut first = HashMap::new();
+    let mut aliases = Vec::new();
+    for (i, range) in ranges.into_iter().enumerate() {
+        if range.1 == 0 {
+            continue;
+        }
+        match first.get(&range) {
+            Some(&original) => aliases.push((i, original)),
+            None => {
+                first.insert(range, i);
+            }
+        }
+    }
+    aliases
+}
+
+/// Finds the position of an entry in a file list.
+pub(crate) fn find_entry(files : &[FarFileInfo], id : EntryId) -> Result<usize> {
+    let index = match id {
+        EntryId::Name(name) => files.iter().position(|info| info.name == name),
+        EntryId::Index(index) => Some(index).filter(|&i| i < files.len()),
+    };
+    index.ok_or_else(|| FarError::NotFound(id.to_string()))
 }
 
 /// Struct containing a file, whether or not it's in an archive.
@@ -17,6 +316,10 @@ pub struct FarFileInfo {
 ///
 /// # Examples
 /// ```
+/// # let buffer = b"hello".to_vec();
+/// # let fileA_name = "a.txt".to_string();
+/// # let archive_buf = b"FAR!byAZ\x01\0\0\0\x15\0\0\0hello\0\0\0\0".to_vec();
+/// # let (fileB_name, fileB_size, fileB_offset) = ("b.txt".to_string(), 5, 16);
 /// // buffer is a Vec<u8> containing the contents of a file
 /// // fileA_name is the name of the file
 /// use libfar::farlib::FarFile;
@@ -26,21 +329,57 @@ pub struct FarFileInfo {
 /// // fileB_name is the name of the file that we got from reading the manifest
 /// // fileB_size is the size of the file that we got from reading the manifest
 /// // fileB_offset is the offset of the file that we got from reading the manifest
-/// let fileB = FarFile::new_from_archive(fileB_name, fileB_size, fileB_offset, archive_buf);
+/// let fileB = FarFile::new_from_archive(fileB_name, fileB_size, fileB_offset, &archive_buf)
+///     .expect("File is outside of the archive");
 /// ```
+#[derive(Debug, Clone)]
 pub struct FarFile {
     pub name: String,
     pub size: u32,
     pub data: Vec<u8>,
 }
 
+/// The two layouts of version 1 manifests, which differ only in how name lengths are stored.
+/// Most archives are 1a, while some of The Sims 1's expansion packs ship 1b archives.
+///
+/// `farlib::test` detects the layout, and `to_vec` writes the same layout back.
+///
+/// # Examples
+/// ```
+/// use libfar::farlib::{self, FarVariant};
+/// let data = b"FAR!byAZ\x01\0\0\0\x15\0\0\0hello";
+/// let entry = b"\x01\0\0\0\x05\0\0\0\x05\0\0\0\x10\0\0\0";
+/// let v1a = [&data[..], entry, b"\x05\0\0\0a.txt"].concat();
+/// let v1b = [&data[..], entry, b"\x05\0a.txt"].concat();
+/// for (buffer, variant) in [(v1a, FarVariant::V1a), (v1b, FarVariant::V1b)] {
+///     let archive = farlib::test(&buffer).expect("Not a valid archive");
+///     assert_eq!(archive.variant, variant);
+///     assert_eq!(archive.file_list[0].name, "a.txt");
+///     assert_eq!(archive.file_list[0].offset, 16);
+///     let archive = archive.load_file_data(&buffer).expect("Failed to load files");
+///     assert_eq!(archive.file_data[0].data, b"hello");
+///     assert_eq!(archive.to_vec(), buffer);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FarVariant {
+    /// Name lengths are stored as a u32.
+    #[default]
+    V1a,
+    /// Name lengths are stored as a u16.
+    V1b,
+}
+
 /// Struct containing information about an archive.
 ///
 /// Should be created by one of two ways:
 /// 1. Calling `FarArchive::new_from_files` if creating an archive from a list of FarFile structs
 /// 2. Calling `farlib::test(buffer)` if loading an archive from a file/buffer
+#[derive(Debug, Clone)]
 pub struct FarArchive {
     pub version: u32,
+    /// Layout of the manifest, only meaningful for version 1 archives.
+    pub variant: FarVariant,
     pub file_count: u32,
     pub file_list: Vec<FarFileInfo>,
     pub file_data: Vec<FarFile>,
@@ -48,31 +387,67 @@ pub struct FarArchive {
 
 impl FarFile {
     /// Creates a new FarFile struct from an offset, size, and archive buffer.
+    /// The data is copied as it is stored in the archive; use `FarFile::new_from_entry` to
+    /// decompress compressed files.
+    /// Returns an error if the file's data does not lie within the archive buffer.
     ///
     /// # Examples
     /// ```
+    /// # let archive_buf = b"FAR!byAZ\x01\0\0\0\x15\0\0\0hello\0\0\0\0".to_vec();
+    /// # let (file_name, file_size, file_offset) = ("a.txt".to_string(), 5, 16);
     /// // archive_buf is a Vec<u8> containing the contents of a .far file
     /// // file_name is the name of the file that we got from reading the manifest
     /// // file_size is the size of the file that we got from reading the manifest
     /// // file_offset is the offset of the file that we got from reading the manifest
     /// use libfar::farlib::FarFile;
-    /// let file = FarFile::new_from_archive(file_name, file_size, file_offset, archive_buf);
+    /// let file = FarFile::new_from_archive(file_name, file_size, file_offset, &archive_buf)
+    ///     .expect("File is outside of the archive");
     /// ```
-    pub fn new_from_archive(name : String, size : u32, offset : u32, original_file : &Vec<u8>) -> FarFile {
-        let mut reader = BufReader::new(&original_file[offset as usize..(offset + size) as usize]);
-        let mut data = Vec::new();
-        reader.read_to_end(&mut data).expect("Failed to read file data");
-        FarFile {
+    pub fn new_from_archive(name : String, size : u32, offset : u32, original_file : &[u8]) -> Result<FarFile> {
+        let start = offset as usize;
+        let data = start.checked_add(size as usize)
+            .and_then(|end| original_file.get(start..end))
+            .ok_or(FarError::EntryOutOfBounds {
+                entry_index: None,
+                offset,
+                size,
+            })?;
+        Ok(FarFile {
             name,
             size,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Creates a new FarFile struct from a manifest entry and archive buffer, decompressing
+    /// the file's data if it is compressed.
+    ///
+    /// # Examples
+    /// ```
+    /// # let archive_buf = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+    /// # ]).to_vec();
+    /// // archive_buf is a Vec<u8> containing the contents of a .far file
+    /// use libfar::farlib::{self, FarFile};
+    /// let archive = farlib::test(&archive_buf).expect("Not a valid archive");
+    /// let file = FarFile::new_from_entry(&archive.file_list[0], &archive_buf).expect("Failed to read file");
+    /// ```
+    pub fn new_from_entry(info : &FarFileInfo, original_file : &[u8]) -> Result<FarFile> {
+        let stored = FarFile::new_from_archive(info.name.clone(), info.stored_size, info.offset, original_file)?;
+        let data = decode_entry(info, stored.data, None)?;
+        Ok(FarFile {
+            name: stored.name,
+            size: data.len() as u32,
             data,
-        }
+        })
     }
 
     /// Creates a new FarFile struct from a size, and data buffer.
     ///
     /// # Examples
     /// ```
+    /// # let buffer = b"hello".to_vec();
+    /// # let file_name = "a.txt".to_string();
     /// // buffer is a Vec<u8> containing the contents of a file
     /// // file_name is the name of the file
     /// use libfar::farlib::FarFile;
@@ -90,9 +465,11 @@ impl FarFile {
 impl FarArchive {
     /// Creates a new FarArchive struct from a list of FarFile structs.
     /// Important when creating a new archive.
+    /// Use `FarArchive::from_directory` to create an archive from a directory tree instead.
     ///
     /// # Examples
-    /// ```
+    /// ```no_run
+    /// # let file_names: Vec<String> = vec![];
     /// // file_names is a Vec<String> containing the names of the files
     /// use std::fs;
     /// use libfar::farlib;
@@ -111,31 +488,82 @@ impl FarArchive {
     ///
     /// let archive = farlib::FarArchive::new_from_files(file_list);
     /// ```
+    ///
+    /// The file list holds the offsets the files are written at by `to_vec`:
+    /// ```
+    /// use libfar::farlib::{self, FarArchive, FarFile};
+    /// let archive = FarArchive::new_from_files(vec![
+    ///     FarFile::new_from_file("a.txt".to_string(), 2, b"hi".to_vec()),
+    ///     FarFile::new_from_file("b.txt".to_string(), 3, b"bye".to_vec()),
+    /// ]);
+    /// let offsets: Vec<u32> = archive.file_list.iter().map(|info| info.offset).collect();
+    /// assert_eq!(offsets, [16, 18]);
+    /// let buffer = archive.to_vec();
+    /// let parsed = farlib::test(&buffer).expect("Not a valid archive");
+    /// assert!(parsed.file_list.iter().map(|info| info.offset).eq(offsets));
+    /// ```
     pub fn new_from_files(files : Vec<FarFile>) -> FarArchive {
         let mut file_list = Vec::new();
         let mut file_data = Vec::new();
-        let mut offset = 0;
+        // files are written one after the other, straight after the header
+        let mut offset = HEADER_LEN;
         for file in files {
-            offset += &file.size;
             file_list.push(FarFileInfo {
                 name: file.name.clone(),
-                size: file.size,
+                uncompressed_size: file.size,
+                stored_size: file.size,
                 offset,
+                v3: None,
+                raw_name: None,
             });
+            offset = offset.saturating_add(file.data.len() as u32);
             file_data.push(file);
         }
         FarArchive {
             version: 1,
+            variant: FarVariant::default(),
             file_count: file_list.len() as u32,
             file_list,
             file_data,
         }
     }
 
+    /// Creates a new FarArchive struct from every file under a directory, read into memory.
+    /// Entry names are the files' paths relative to `path`, separated with backslashes, and
+    /// files are added in a deterministic order (sorted by name).
+    /// `options` can limit which files are added, see `DirectoryOptions` and `directory::walk`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::fs;
+    /// use libfar::directory::DirectoryOptions;
+    /// use libfar::farlib::FarArchive;
+    /// let options = DirectoryOptions {
+    ///     exclude: vec!["*.bak".to_string()],
+    ///     ignore_file: Some(".farignore".to_string()),
+    ///     ..Default::default()
+    /// };
+    /// let archive = FarArchive::from_directory("my_mod", &options).expect("Failed to read directory");
+    /// fs::write("my_mod.far", archive.to_vec()).expect("Failed to write file");
+    /// ```
+    pub fn from_directory(path : impl AsRef<Path>, options : &DirectoryOptions) -> Result<FarArchive> {
+        let mut files = Vec::new();
+        for (name, path) in directory::walk(path, options)? {
+            let data = std::fs::read(path)?;
+            let size = u32::try_from(data.len()).map_err(|_| FarError::ArchiveTooLarge)?;
+            files.push(FarFile::new_from_file(name, size, data));
+        }
+        Ok(FarArchive::new_from_files(files))
+    }
+
     /// Loads file data into a FarArchive struct, used if a FarFileInfo struct is not sufficient.
+    /// Compressed files are decompressed.
+    /// Returns an error if any file's data does not lie within the archive buffer.
     ///
     /// # Examples
     /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![]).to_vec();
+    /// # let archive_name = "test.far";
     /// // buffer is a Vec<u8> containing the contents of a .far file
     /// use libfar::farlib;
     /// let test = farlib::test(&buffer);
@@ -148,29 +576,321 @@ impl FarArchive {
     ///   }
     /// }
     /// ```
-    pub fn load_file_data(self, original_file : &Vec<u8>) -> FarArchive {
+    pub fn load_file_data(self, original_file : &[u8]) -> Result<FarArchive> {
+        self.load(original_file, false)
+    }
+
+    /// Loads file data into a FarArchive struct exactly as it is stored in the archive,
+    /// without decompressing compressed files.
+    pub fn load_file_data_raw(self, original_file : &[u8]) -> Result<FarArchive> {
+        self.load(original_file, true)
+    }
+
+    fn load(self, original_file : &[u8], raw : bool) -> Result<FarArchive> {
         let mut new_file_data = Vec::new();
-        for i in 0..self.file_list.len() {
-            new_file_data.push(FarFile::new_from_archive(
-                self.file_list[i].name.clone(),
-                self.file_list[i].size,
-                self.file_list[i].offset,
-                original_file,
-            ));
+        for (i, info) in self.file_list.iter().enumerate() {
+            let mut file = FarFile::new_from_archive(info.name.clone(), info.stored_size, info.offset, original_file)
+                .map_err(|_| FarError::EntryOutOfBounds {
+                    entry_index: Some(i as u32),
+                    offset: info.offset,
+                    size: info.stored_size,
+                })?;
+            if !raw {
+                file.data = decode_entry(info, file.data, Some(i as u32))?;
+                file.size = file.data.len() as u32;
+            }
+            new_file_data.push(file);
         }
-        FarArchive {
+        Ok(FarArchive {
             version: self.version,
+            variant: self.variant,
             file_count: self.file_count,
             file_list: self.file_list,
             file_data: new_file_data,
+        })
+    }
+
+    /// Returns the position of an entry in the archive's file list, if it exists.
+    pub fn index_of<'a>(&self, id : impl Into<EntryId<'a>>) -> Option<usize> {
+        find_entry(&self.file_list, id.into()).ok()
+    }
+
+    /// Builds a `NameIndex` over the archive's file list, for looking up many names quickly,
+    /// ignoring case and slash direction, or by prefix.
+    /// The index borrows the archive, so build it again after editing the archive.
+    pub fn index(&self) -> NameIndex<'_> {
+        NameIndex::new(&self.file_list)
+    }
+
+    /// Builds a `FarTree` over the archive's file list, for browsing it as directories.
+    /// Like `index`, the tree borrows the archive.
+    pub fn tree(&self) -> FarTree<'_> {
+        FarTree::new(&self.file_list)
+    }
+
+    /// Returns the entries that share their data with an earlier entry, as pairs of
+    /// (entry, earlier entry) indices. Archives written with `WriteOptions::dedup` point
+    /// identical files at the same data, which is fine to read and isn't a sign of corruption.
+    ///
+    /// # Examples
+    /// ```
+    /// use libfar::farlib::{self, FarArchive, FarFile};
+    /// use libfar::writer::WriteOptions;
+    /// let palette = vec![0; 768];
+    /// let archive = FarArchive::new_from_files(vec![
+    ///     FarFile::new_from_file("a.pal".to_string(), 768, palette.clone()),
+    ///     FarFile::new_from_file("b.pal".to_string(), 768, palette),
+    /// ]);
+    /// let buffer = archive.to_vec_with(WriteOptions { dedup: true, ..Default::default() });
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// assert_eq!(archive.aliases(), vec![(1, 0)]);
+    /// ```
+    pub fn aliases(&self) -> Vec<(usize, usize)> {
+        find_aliases(self.file_list.iter().map(|info| (info.offset, info.stored_size)))
+    }
+
+    /// Adds a file to the end of the archive, or replaces the data of the file with the same name
+    /// if there already is one, returning the old file.
+    /// Returns `FarError::DataNotLoaded` if the archive holds files but not their data, since
+    /// the file list and data would no longer line up.
+    ///
+    /// The editing methods (`insert`, `remove`, `rename`, `replace_data` and `retain`) keep
+    /// `file_list`, `file_count` and `file_data` in sync, so the result can be written with
+    /// `to_vec` straight away.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("old.txt".to_string(), 3, b"old".to_vec()),
+    /// #     libfar::farlib::FarFile::new_from_file("unused.txt".to_string(), 0, vec![]),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use libfar::farlib::{self, FarFile};
+    /// let mut archive = farlib::test(&buffer).expect("Not a valid archive")
+    ///     .load_file_data(&buffer).expect("Failed to load files");
+    /// archive.insert(FarFile::new_from_file("patch.txt".to_string(), 5, b"patch".to_vec()))
+    ///     .expect("Failed to add file");
+    /// archive.rename("old.txt", "new.txt").expect("Failed to rename file");
+    /// archive.replace_data("new.txt", b"new".to_vec()).expect("Failed to replace file");
+    /// archive.remove("unused.txt").expect("Failed to remove file");
+    /// archive.retain(|info| !info.name.ends_with(".bak"));
+    /// let patched = archive.to_vec();
+    /// ```
+    pub fn insert(&mut self, file : FarFile) -> Result<Option<FarFile>> {
+        if let Some(index) = self.index_of(&file.name) {
+            let old_data = self.replace_data(index, file.data)?;
+            let old_name = self.file_list[index].name.clone();
+            return Ok(Some(FarFile::new_from_file(old_name, old_data.len() as u32, old_data)));
+        }
+        self.check_data_loaded()?;
+        let size = u32::try_from(file.data.len()).map_err(|_| FarError::ArchiveTooLarge)?;
+        self.file_list.push(FarFileInfo {
+            name: file.name.clone(),
+            uncompressed_size: size,
+            stored_size: size,
+            offset: 0,
+            v3: (self.version == 3).then(FarV3Info::default),
+            raw_name: None,
+        });
+        self.file_data.push(FarFile { size, ..file });
+        self.file_count = self.file_list.len() as u32;
+        Ok(None)
+    }
+
+    /// Removes a file from the archive, returning it (without data if none was loaded).
+    pub fn remove<'a>(&mut self, id : impl Into<EntryId<'a>>) -> Result<FarFile> {
+        let index = find_entry(&self.file_list, id.into())?;
+        let info = self.file_list.remove(index);
+        self.file_count = self.file_list.len() as u32;
+        if index < self.file_data.len() {
+            return Ok(self.file_data.remove(index));
         }
+        Ok(FarFile::new_from_file(info.name, info.uncompressed_size, vec![]))
+    }
+
+    /// Renames a file, keeping its data and position in the archive.
+    /// Returns `FarError::AlreadyExists` if another file already has the new name.
+    pub fn rename<'a>(&mut self, id : impl Into<EntryId<'a>>, new_name : impl Into<String>) -> Result<()> {
+        let index = find_entry(&self.file_list, id.into())?;
+        let new_name = new_name.into();
+        if self.index_of(&new_name).is_some_and(|existing| existing != index) {
+            return Err(FarError::AlreadyExists(new_name));
+        }
+        if let Some(file) = self.file_data.get_mut(index) {
+            file.name = new_name.clone();
+        }
+        self.file_list[index].name = new_name;
+        self.file_list[index].raw_name = None;
+        Ok(())
+    }
+
+    /// Replaces a file's data, returning the old data. The file is written uncompressed (unless
+    /// `to_vec_with` is told to compress it), and keeps its name and position in the archive.
+    /// Returns `FarError::DataNotLoaded` if the archive's file data hasn't been loaded.
+    pub fn replace_data<'a>(&mut self, id : impl Into<EntryId<'a>>, data : Vec<u8>) -> Result<Vec<u8>> {
+        let index = find_entry(&self.file_list, id.into())?;
+        self.check_data_loaded()?;
+        let size = u32::try_from(data.len()).map_err(|_| FarError::ArchiveTooLarge)?;
+        let info = &mut self.file_list[index];
+        info.uncompressed_size = size;
+        info.stored_size = size;
+        if let Some(v3) = &mut info.v3 {
+            v3.data_type = 0;
+            v3.compressed = 0;
+        }
+        let file = &mut self.file_data[index];
+        file.size = size;
+        Ok(std::mem::replace(&mut file.data, data))
+    }
+
+    /// Keeps only the files for which `keep` returns true, removing the rest.
+    pub fn retain(&mut self, mut keep : impl FnMut(&FarFileInfo) -> bool) {
+        let keep: Vec<bool> = self.file_list.iter().map(&mut keep).collect();
+        let mut flags = keep.iter();
+        self.file_list.retain(|_| *flags.next().unwrap());
+        if !self.file_data.is_empty() {
+            let mut flags = keep.iter();
+            self.file_data.retain(|_| flags.next().copied().unwrap_or(true));
+        }
+        self.file_count = self.file_list.len() as u32;
+    }
+
+    /// Checks that `file_data` lines up with `file_list`, which editing the data relies on.
+    fn check_data_loaded(&self) -> Result<()> {
+        if self.file_data.len() != self.file_list.len() {
+            return Err(FarError::DataNotLoaded);
+        }
+        Ok(())
+    }
+
+    /// Reads a single file out of the archive, without loading the data of any other file.
+    /// `source` is the archive the FarArchive struct was read from.
+    /// Compressed files are decompressed.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("texture.bmp".to_string(), 3, vec![1, 2, 3]),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use std::io::Cursor;
+    /// use libfar::farlib;
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// let file = archive.extract("texture.bmp", &mut Cursor::new(&buffer)).expect("Failed to extract file");
+    /// let first = archive.extract(0, &mut Cursor::new(&buffer)).expect("Failed to extract file");
+    /// ```
+    pub fn extract<'a, S : Read + Seek>(&self, id : impl Into<EntryId<'a>>, source : &mut S) -> Result<FarFile> {
+        let index = find_entry(&self.file_list, id.into())?;
+        let info = &self.file_list[index];
+        let data = reader::read_entry(source, info, index)?;
+        Ok(FarFile {
+            name: info.name.clone(),
+            size: data.len() as u32,
+            data,
+        })
+    }
+
+    /// Reads a single file out of the archive exactly as it is stored, without decompressing it.
+    pub fn extract_raw<'a, S : Read + Seek>(&self, id : impl Into<EntryId<'a>>, source : &mut S) -> Result<FarFile> {
+        let index = find_entry(&self.file_list, id.into())?;
+        let info = &self.file_list[index];
+        let mut data = Vec::new();
+        reader::open_entry(source, info, index)?.read_to_end(&mut data)?;
+        Ok(FarFile {
+            name: info.name.clone(),
+            size: data.len() as u32,
+            data,
+        })
+    }
+
+    /// Returns a reader over a single file's data within `source`.
+    /// Uncompressed files are streamed without reading them into memory first, while compressed
+    /// files are decompressed into memory and read from there.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("texture.bmp".to_string(), 3, vec![1, 2, 3]),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use std::io::{self, Cursor};
+    /// use libfar::farlib;
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// let mut source = Cursor::new(&buffer);
+    /// let mut reader = archive.reader_for("texture.bmp", &mut source).expect("Failed to find file");
+    /// io::copy(&mut reader, &mut io::sink()).expect("Failed to read file");
+    /// ```
+    pub fn reader_for<'a, 's, S : Read + Seek>(&self, id : impl Into<EntryId<'a>>, source : &'s mut S) -> Result<EntryReader<'s, S>> {
+        let index = find_entry(&self.file_list, id.into())?;
+        reader::open_entry_decoded(source, &self.file_list[index], index)
+    }
+
+    /// Returns a reader limited to a single file's stored bytes within `source`, without
+    /// decompressing them.
+    pub fn raw_reader_for<'a, 's, S : Read + Seek>(&self, id : impl Into<EntryId<'a>>, source : &'s mut S) -> Result<io::Take<&'s mut S>> {
+        let index = find_entry(&self.file_list, id.into())?;
+        reader::open_entry(source, &self.file_list[index], index)
+    }
+
+    /// Writes every loaded file to disk under `dest_dir`, returning the paths that were written.
+    /// Backslashes in names are treated as directory separators, and missing directories are
+    /// created. Files are written from `file_data`, so load it with `load_file_data` first;
+    /// returns `FarError::DataNotLoaded` if it hasn't been loaded.
+    ///
+    /// Names that would escape `dest_dir` are rejected or sanitized according to `options`,
+    /// and symlinks inside `dest_dir` are never followed. Files that already exist are
+    /// overwritten, skipped or renamed according to `options`; skipped files aren't returned.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use libfar::extract::{ExtractOptions, OnConflict};
+    /// use libfar::farlib;
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive")
+    ///     .load_file_data(&buffer).expect("Failed to load files");
+    /// let options = ExtractOptions {
+    ///     on_conflict: OnConflict::Skip,
+    ///     ..Default::default()
+    /// };
+    /// let written = archive.extract_all("out", &options).expect("Failed to extract archive");
+    /// ```
+    ///
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+    /// # ]).to_vec();
+    /// use libfar::extract::ExtractOptions;
+    /// use libfar::farlib::{self, FarError};
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// // nothing is extracted until the data is loaded
+    /// let result = archive.extract_all("out", &ExtractOptions::default());
+    /// assert!(matches!(result, Err(FarError::DataNotLoaded)));
+    /// ```
+    pub fn extract_all(&self, dest_dir : impl AsRef<Path>, options : &ExtractOptions) -> Result<Vec<PathBuf>> {
+        self.check_data_loaded()?;
+        let mut written = Vec::new();
+        for file in &self.file_data {
+            let path = extract::extract_entry(dest_dir.as_ref(), &file.name, options, |output| {
+                Ok(output.write_all(&file.data)?)
+            })?;
+            written.extend(path);
+        }
+        Ok(written)
     }
 
     /// Creates a buffer representing the contents of a FarArchive struct.
     /// Can be written to a file to create a .far archive.
+    /// Use `writer::FarWriter` instead to write large archives without holding them in memory.
+    ///
+    /// # Panics
+    /// Panics if the archive can't be written, for any of the reasons `try_to_vec` returns an
+    /// error. Use `try_to_vec` for archives that didn't come from a trusted source.
     ///
     /// # Examples
-    /// ```
+    /// ```no_run
+    /// # let archive = libfar::farlib::FarArchive::new_from_files(vec![]);
+    /// # let archive_name = "test.far".to_string();
     /// // archive is a FarArchive struct
     /// // archive_name is the name of the file we will write the archive to
     /// use std::fs;
@@ -178,61 +898,97 @@ impl FarArchive {
     /// use libfar::farlib;
     /// let buffer = archive.to_vec();
     /// let mut file = fs::File::create(archive_name.clone()).expect("Failed to create file");
-    /// file.write_all(&*archive_obj.to_vec()).expect("Failed to write file");
+    /// file.write_all(&buffer).expect("Failed to write file");
     /// ```
     pub fn to_vec(self) -> Vec<u8> {
-        // write header
-        let mut header = Vec::new();
-        for c in "FAR!byAZ".chars() {
-            header.push(c as u8);
-        }
-        header.extend(&self.version.to_le_bytes());
-        // wait to write manifest offset until calculated later
-        // write file data
-        let mut file_data = Vec::new(); // actual data to be written to file
-        let mut file_list = Vec::new(); // file list used for making manifest later on
-        let mut bytes_written = 16; // where we should start putting files
-        for i in 0..self.file_data.len() {
-            let mut file_data_bytes = Vec::new();
-            file_data_bytes.extend_from_slice(&self.file_data[i].data);
-            file_data.extend_from_slice(&file_data_bytes);
-            file_list.push(FarFileInfo {
-                name: self.file_data[i].name.clone(),
-                size: self.file_data[i].size,
-                offset: bytes_written,
-            });
-            bytes_written += self.file_data[i].size;
-        }
-        // write manifest
-        let mut manifest = Vec::new();
-        // write file count
-        manifest.extend_from_slice(&self.file_count.to_le_bytes());
-        // for each file, write (size, size, offset, name length, name)
-        for i in 0..self.file_list.len() {
-            manifest.extend_from_slice(&file_list[i].size.to_le_bytes());
-            manifest.extend_from_slice(&file_list[i].size.to_le_bytes());
-            manifest.extend_from_slice(&file_list[i].offset.to_le_bytes());
-            manifest.extend_from_slice(&(file_list[i].name.len() as u32).to_le_bytes());
-            manifest.extend_from_slice(&file_list[i].name.as_bytes());
-        }
-        // write manifest offset
-        let manifest_offset = bytes_written;
-        header.extend_from_slice(&manifest_offset.to_le_bytes());
+        self.try_to_vec().expect("Failed to write archive")
+    }
+
+    /// Like `to_vec`, but writes the archive according to `options`, e.g. to compress files.
+    ///
+    /// # Panics
+    /// Panics under the same conditions as `to_vec`.
+    pub fn to_vec_with(self, options : WriteOptions) -> Vec<u8> {
+        self.try_to_vec_with(options).expect("Failed to write archive")
+    }
+
+    /// Creates a buffer representing the contents of a FarArchive struct, like `to_vec`.
+    /// Returns an error if the archive's version is not 1 or 3, if the archive would be too
+    /// large for the FAR format (4 GiB in total, or 16 MiB per file in version 3), or if a name
+    /// can't be stored (see `WriteOptions::names`).
+    /// Returns `FarError::DataNotLoaded` if the archive holds files but not their data, as
+    /// returned by `farlib::test` before calling `load_file_data`.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+    /// # ]).to_vec();
+    /// use libfar::farlib::{self, FarArchive, FarError, FarFile, FarVariant};
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// assert!(matches!(archive.try_to_vec(), Err(FarError::DataNotLoaded)));
+    /// let archive = archive.load_file_data(&buffer).expect("Failed to load files");
+    /// assert_eq!(archive.try_to_vec().expect("Failed to write archive"), buffer);
+    ///
+    /// let mut archive = FarArchive::new_from_files(vec![FarFile::new_from_file("a".repeat(70000), 0, vec![])]);
+    /// archive.variant = FarVariant::V1b;
+    /// // version 1b stores name lengths as a u16
+    /// assert!(matches!(archive.try_to_vec(), Err(FarError::InvalidName { entry_index: 0 })));
+    /// ```
+    pub fn try_to_vec(&self) -> Result<Vec<u8>> {
+        self.try_to_vec_with(WriteOptions::default())
+    }
 
-        // join vecs together
-        let mut output = Vec::new();
-        output.extend_from_slice(&header);
-        output.extend_from_slice(&file_data);
-        output.extend_from_slice(&manifest);
-        output
+    /// Like `try_to_vec`, but writes the archive according to `options`, e.g. to compress files.
+    ///
+    /// Files whose data was loaded with `load_file_data_raw` are written back exactly as they
+    /// were stored, keeping both manifest sizes, so re-packing an archive doesn't alter them.
+    /// `options` only applies to the remaining files, and the archive's own `variant` is always
+    /// used in place of `options.variant`.
+    ///
+    /// # Examples
+    /// ```
+    /// use libfar::farlib::{FarArchive, FarFile};
+    /// use libfar::writer::{CompressionPolicy, WriteOptions};
+    /// let archive = FarArchive::new_from_files(vec![FarFile::new_from_file("a.txt".to_string(), 64, vec![0; 64])]);
+    /// let options = WriteOptions {
+    ///     compression: CompressionPolicy::IfSmaller,
+    ///     ..Default::default()
+    /// };
+    /// let buffer = archive.try_to_vec_with(options).expect("Failed to write archive");
+    /// ```
+    pub fn try_to_vec_with(&self, options : WriteOptions) -> Result<Vec<u8>> {
+        let options = WriteOptions {
+            variant: self.variant,
+            ..options
+        };
+        self.check_data_loaded()?;
+        let mut writer = FarWriter::with_options(io::Cursor::new(Vec::new()), self.version, options)?;
+        for (i, file) in self.file_data.iter().enumerate() {
+            let info = self.file_list.get(i).filter(|info| info.name == file.name);
+            match info {
+                // files loaded with `load_file_data_raw` still hold their stored bytes, so write
+                // them back with the manifest entry they were read with
+                Some(info) if info.stored_size != info.uncompressed_size && file.data.len() == info.stored_size as usize => {
+                    writer.add_file_raw(info, &file.data[..])?
+                }
+                // keep the version 3 fields of files that were loaded from an archive
+                _ => {
+                    let v3 = info.and_then(|info| info.v3).unwrap_or_default();
+                    let raw_name = info.and_then(|info| info.raw_name.clone());
+                    writer.add_entry(file.name.clone(), raw_name, &file.data[..], v3)?
+                }
+            }
+        }
+        Ok(writer.finish()?.into_inner())
     }
 }
 
 /// Tests if a buffer is a valid FarArchive.
-/// Returns a FarArchive struct if it is, or an error if it is not.
+/// Returns a FarArchive struct if it is, or a `FarError` describing the problem if it is not.
 ///
 /// # Examples
-/// ```
+/// ```no_run
 /// use std::fs;
 /// use libfar::farlib;
 /// let buffer = fs::read("test.far").expect("Failed to read file");
@@ -246,60 +1002,276 @@ impl FarArchive {
 ///     }
 /// }
 /// ```
-pub fn test(file : &Vec<u8>) -> Result<FarArchive, String> {
-    let mut reader = BufReader::new(&file[..]);
-    let mut magic = [0u8; 8];
-    reader.read_exact(&mut magic).unwrap();
-    if magic != *b"FAR!byAZ" {
-        return Err("Not a Far archive".to_string());
-    }
-    let mut version = [0; 4];
-    reader.read_exact(&mut version).unwrap();
-    let version = u32::from_le_bytes(version);
+///
+/// An archive cut off in the middle of its manifest is reported as truncated:
+/// ```
+/// use libfar::farlib::{self, FarArchive, FarError, FarFile};
+/// let buffer = FarArchive::new_from_files(vec![
+///     FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+///     FarFile::new_from_file("b.txt".to_string(), 5, b"world".to_vec()),
+/// ]).to_vec();
+/// // the last entry of the manifest takes up the last 21 bytes
+/// for cut in 1..=21 {
+///     let truncated = &buffer[..buffer.len() - cut];
+///     assert!(matches!(farlib::test(truncated), Err(FarError::Truncated { entry_index: Some(1), .. })));
+/// }
+/// ```
+pub fn test(file : &[u8]) -> Result<FarArchive> {
+    test_with(file, NameEncoding::default())
+}
+
+/// Like `test`, but decodes entry names with `names` instead of requiring them to be UTF-8.
+///
+/// # Examples
+/// ```
+/// # let mut writer = libfar::writer::FarWriter::with_options(std::io::Cursor::new(Vec::new()), 1,
+/// #     libfar::writer::WriteOptions { names: libfar::encoding::NameEncoding::Windows1252, ..Default::default() }).unwrap();
+/// # writer.add_bytes("caf\u{e9}.iff", b"").unwrap();
+/// # writer.add_bytes("chair.iff", b"").unwrap();
+/// # let buffer = writer.finish().unwrap().into_inner();
+/// // buffer is a Vec<u8> containing the contents of a .far file from The Sims
+/// use libfar::encoding::NameEncoding;
+/// use libfar::farlib;
+/// let archive = farlib::test_with(&buffer, NameEncoding::Windows1252).expect("Not a valid archive");
+/// assert_eq!(archive.file_list[0].name, "café.iff");
+/// // the stored bytes of names that aren't UTF-8 are kept, so the archive is written back as it was
+/// assert_eq!(archive.file_list[0].raw_name.as_deref(), Some(&b"caf\xe9.iff"[..]));
+/// assert_eq!(archive.file_list[1].raw_name, None);
+/// let archive = archive.load_file_data(&buffer).expect("Failed to load files");
+/// assert_eq!(archive.to_vec(), buffer);
+/// ```
+pub fn test_with(file : &[u8], names : NameEncoding) -> Result<FarArchive> {
+    let (version, _) = parse_header(file)?;
     // get list of files
-    let files = list_files(file).expect("Failed to list files");
+    let (variant, files) = list_files(file, names)?;
     Ok(FarArchive {
         version,
+        variant,
         file_count: files.len() as u32,
         file_list: files,
         file_data: vec![],
     })
 }
 
-fn list_files(file : &Vec<u8>) -> Result<Vec<FarFileInfo>, String> {
+/// Size of the archive header, which comes before any file data or the manifest.
+pub(crate) const HEADER_LEN: u32 = 16;
+
+/// Parses the 16 byte archive header, returning the version and manifest offset.
+/// A manifest offset inside the header is rejected, as it can never be valid.
+pub(crate) fn parse_header(header : &[u8]) -> Result<(u32, u32)> {
+    let mut reader = ByteReader::new(header, 0, 0);
+    let magic = reader.read_bytes(8)?;
+    if magic != b"FAR!byAZ" {
+        return Err(FarError::BadMagic);
+    }
+    let version = reader.read_u32()?;
+    if version != 1 && version != 3 {
+        return Err(FarError::UnsupportedVersion(version));
+    }
     // manifest offset is at 12 bytes (u32)
-    let mut reader = BufReader::new(&file[12..]);
-    let mut offset = [0u8; 4];
-    reader.read_exact(&mut offset).unwrap();
-    let offset = u32::from_le_bytes(offset);
+    let manifest_offset = reader.read_u32()?;
+    if manifest_offset < HEADER_LEN {
+        return Err(FarError::ManifestOutOfBounds { offset: manifest_offset });
+    }
+    Ok((version, manifest_offset))
+}
+
+fn list_files(file : &[u8], names : NameEncoding) -> Result<(FarVariant, Vec<FarFileInfo>)> {
+    let (version, offset) = parse_header(file)?;
+    if offset as usize > file.len() {
+        return Err(FarError::ManifestOutOfBounds { offset });
+    }
     // move to manifest
-    reader = BufReader::new(&file[offset as usize..]);
-    // read u32 for number of files
-    let mut num_files = [0u8; 4];
-    reader.read_exact(&mut num_files).unwrap();
-    let num_files = u32::from_le_bytes(num_files);
-    // for each file, read u32 for size, u32 for size again (stored twice for some reason), u32 for offset, u32 for name length, name
+    parse_manifest(&file[offset as usize..], offset as u64, version, names)
+}
+
+/// Parses a manifest, where `manifest` holds everything from the manifest offset (`base`) onwards
+/// to the end of the archive, returning the detected variant along with the files.
+pub(crate) fn parse_manifest(manifest : &[u8], base : u64, version : u32, names : NameEncoding) -> Result<(FarVariant, Vec<FarFileInfo>)> {
+    let variant = detect_variant(manifest, base, version)?;
     let mut files = Vec::new();
-    for i in 0..num_files {
-        let mut size = [0u8; 4];
-        reader.read_exact(&mut size).expect(format!("Failed to read size for file {}", i).as_str());
-        let size = u32::from_le_bytes(size);
-        let mut size2 = [0u8; 4];
-        reader.read_exact(&mut size2).expect(format!("Failed to read size for file {}", i).as_str());
-        let _size2 = u32::from_le_bytes(size2); // why is this stored twice? f*** you EA
-        let mut offset = [0u8; 4];
-        reader.read_exact(&mut offset).expect(format!("Failed to read offset for file {}", i).as_str());
-        let offset = u32::from_le_bytes(offset);
-        let mut name_len = [0u8; 4];
-        reader.read_exact(&mut name_len).expect(format!("Failed to read name length for file {}", i).as_str());
-        let name_len = u32::from_le_bytes(name_len);
-        let mut name = vec![0u8; name_len as usize];
-        reader.read_exact(&mut name).expect(format!("Failed to read name for file {}", i).as_str());
-        files.push(FarFileInfo {
-            name: String::from_utf8(name).unwrap(),
-            size,
-            offset,
+    for (i, entry) in ManifestEntries::new(manifest, base, version, variant)?.enumerate() {
+        files.push(entry?.to_info(i as u32, names)?);
+    }
+    Ok((variant, files))
+}
+
+/// Works out the variant of a version 1 manifest by trying both layouts, preferring one that
+/// uses up the manifest exactly and only points into the archive before the manifest.
+/// Failing that, the 1a layout is used if it parses, and otherwise the 1b layout only if it
+/// parses, uses up the manifest exactly and has no NUL bytes in its names, as a 1a manifest that
+/// is cut off can still parse as 1b (with the high bytes of each name length read as part of the
+/// name). If neither applies, the error from the 1a layout is returned.
+pub(crate) fn detect_variant(manifest : &[u8], base : u64, version : u32) -> Result<FarVariant> {
+    if version == 3 {
+        return Ok(FarVariant::default());
+    }
+    let mut parsed = Vec::new();
+    for variant in [FarVariant::V1a, FarVariant::V1b] {
+        let mut entries = ManifestEntries::new(manifest, base, version, variant)?;
+        // whether every entry points before the manifest, and whether no name has a NUL byte
+        let checks = entries.by_ref().try_fold((true, true), |(fits, clean), entry| {
+            entry.map(|entry| (
+                fits && entry.offset as u64 + entry.stored_size as u64 <= base,
+                clean && !entry.name.contains(&0),
+            ))
         });
+        let exact = entries.consumed() == manifest.len();
+        if checks.as_ref().is_ok_and(|&(fits, _)| fits && exact) {
+            return Ok(variant);
+        }
+        parsed.push(checks.map(|(_, clean)| clean && exact));
     }
-    Ok(files)
-}
\ No newline at end of file
+    match (parsed.remove(0), parsed.remove(0)) {
+        (Ok(_), _) => Ok(FarVariant::V1a),
+        (Err(_), Ok(true)) => Ok(FarVariant::V1b),
+        (Err(e), _) => Err(e),
+    }
+}
+
+/// Returns true if `manifest`, the start of a manifest, holds enough of it to be parsed the same
+/// as the whole manifest: every layout's entries can be read from it, and end before it does, so
+/// nothing after it can change which layout `detect_variant` picks.
+pub(crate) fn holds_manifest(manifest : &[u8], base : u64, version : u32) -> bool {
+    [FarVariant::V1a, FarVariant::V1b].into_iter().all(|variant| {
+        ManifestEntries::new(manifest, base, version, variant).is_ok_and(|mut entries| {
+            entries.by_ref().all(|entry| entry.is_ok()) && entries.consumed() < manifest.len()
+        })
+    })
+}
+
+/// A manifest entry as it is stored, with its name not yet decoded.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RawEntry<'a> {
+    pub(crate) name: &'a [u8],
+    pub(crate) uncompressed_size: u32,
+    pub(crate) stored_size: u32,
+    pub(crate) offset: u32,
+    pub(crate) v3: Option<FarV3Info>,
+}
+
+impl RawEntry<'_> {
+    pub(crate) fn to_info(self, index : u32, names : NameEncoding) -> Result<FarFileInfo> {
+        // only names that aren't stored as UTF-8 need their bytes kept to be written back
+        let (name, raw_name) = match names.decode(self.name).ok_or(FarError::InvalidName { entry_index: index })? {
+            Cow::Borrowed(name) => (name.to_string(), None),
+            Cow::Owned(name) => (name, Some(self.name.to_vec())),
+        };
+        Ok(FarFileInfo {
+            name,
+            uncompressed_size: self.uncompressed_size,
+            stored_size: self.stored_size,
+            offset: self.offset,
+            v3: self.v3,
+            raw_name,
+        })
+    }
+}
+
+/// Iterator over the entries of a manifest, borrowing from the manifest buffer.
+/// Stops after the first entry that fails to parse.
+pub(crate) struct ManifestEntries<'a> {
+    reader: ByteReader<'a>,
+    version: u32,
+    variant: FarVariant,
+    len: u32,
+    index: u32,
+}
+
+impl<'a> ManifestEntries<'a> {
+    pub(crate) fn new(manifest : &'a [u8], base : u64, version : u32, variant : FarVariant) -> Result<ManifestEntries<'a>> {
+        let mut reader = ByteReader::new(manifest, base, 0);
+        // read u32 for number of files
+        let len = reader.read_u32()?;
+        Ok(ManifestEntries {
+            reader,
+            version,
+            variant,
+            len,
+            index: 0,
+        })
+    }
+
+    /// Number of entries the manifest claims to have.
+    pub(crate) fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Number of entries returned so far.
+    pub(crate) fn consumed_entries(&self) -> u32 {
+        self.index
+    }
+
+    /// Number of manifest bytes parsed so far.
+    pub(crate) fn consumed(&self) -> usize {
+        self.reader.pos
+    }
+}
+
+impl<'a> Iterator for ManifestEntries<'a> {
+    type Item = Result<RawEntry<'a>>;
+
+    fn next(&mut self) -> Option<Result<RawEntry<'a>>> {
+        if self.index >= self.len {
+            return None;
+        }
+        self.reader.entry_index = Some(self.index);
+        let entry = match self.version {
+            3 => parse_entry_v3(&mut self.reader),
+            _ => parse_entry_v1(&mut self.reader, self.variant),
+        };
+        // there's no telling where the next entry starts after a bad one
+        self.index = if entry.is_ok() { self.index + 1 } else { self.len };
+        Some(entry)
+    }
+}
+
+fn parse_entry_v1<'a>(reader : &mut ByteReader<'a>, variant : FarVariant) -> Result<RawEntry<'a>> {
+    // read u32 for size, u32 for stored size, u32 for offset, u32 (1a) or u16 (1b) for name length, name
+    // the second size is the compressed size, and only differs from the first for compressed files
+    let uncompressed_size = reader.read_u32()?;
+    let stored_size = reader.read_u32()?;
+    let offset = reader.read_u32()?;
+    let name_len = match variant {
+        FarVariant::V1a => reader.read_u32()?,
+        FarVariant::V1b => reader.read_u16()? as u32,
+    };
+    // read_bytes checks the length against the buffer, so a bogus length can't make us allocate
+    let name = reader.read_bytes(name_len as usize)?;
+    Ok(RawEntry {
+        name,
+        uncompressed_size,
+        stored_size,
+        offset,
+        v3: None,
+    })
+}
+
+fn parse_entry_v3<'a>(reader : &mut ByteReader<'a>) -> Result<RawEntry<'a>> {
+    // read u32 for decompressed size, u24 for compressed size, u8 for data type, u32 for offset,
+    // u8 for compression flag, u8 for access number, u16 for name length, u32 for type id,
+    // u32 for file id, name
+    let uncompressed_size = reader.read_u32()?;
+    let stored_size = reader.read_u24()?;
+    let data_type = reader.read_u8()?;
+    let offset = reader.read_u32()?;
+    let compressed = reader.read_u8()?;
+    let access_number = reader.read_u8()?;
+    let name_len = reader.read_u16()?;
+    let type_id = reader.read_u32()?;
+    let file_id = reader.read_u32()?;
+    let name = reader.read_bytes(name_len as usize)?;
+    Ok(RawEntry {
+        name,
+        uncompressed_size,
+        stored_size,
+        offset,
+        v3: Some(FarV3Info {
+            data_type,
+            compressed,
+            access_number,
+            type_id,
+            file_id,
+        }),
+    })
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::farlib::FarFileInfo;
+
+/// Normalises an entry name the way the games compare them: lowercased, with forward slashes
+/// turned into backslashes.
+///
+/// # Examples
+/// ```
+/// use libfar::index::normalize;
+/// assert_eq!(normalize("Objects/Chairs\\Chair01.IFF"), "objects\\chairs\\chair01.iff");
+/// ```
+pub fn normalize(name : &str) -> String {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == '/' { '\\' } else { c })
+        .collect()
+}
+
+/// Lookup index over an archive's entry names, built by `FarArchive::index` or
+/// `FarReader::index`. Finding an entry by name with `index_of` scans the whole file list,
+/// whereas the index is built once and then finds names without scanning.
+///
+/// Lookups return positions in the file list, which can be passed anywhere an `EntryId` is
+/// expected. When several entries match, the one listed first in the manifest wins, as with
+/// `index_of`.
+#[derive(Debug, Clone)]
+pub struct NameIndex<'a> {
+    exact: HashMap<&'a str, usize>,
+    /// Normalised names and their entries, sorted by name and then by position.
+    normalized: Vec<(String, usize)>,
+}
+
+impl<'a> NameIndex<'a> {
+    /// Builds an index over `files`.
+    pub fn new(files : &'a [FarFileInfo]) -> NameIndex<'a> {
+        let mut exact = HashMap::with_capacity(files.len());
+        for (i, info) in files.iter().enumerate() {
+            exact.entry(info.name.as_str()).or_insert(i);
+        }
+        let mut normalized: Vec<(String, usize)> = files.iter()
+            .enumerate()
+            .map(|(i, info)| (normalize(&info.name), i))
+            .collect();
+        normalized.sort_unstable();
+        NameIndex { exact, normalized }
+    }
+
+    /// Returns the entry whose name is exactly `name`.
+    pub fn get(&self, name : &str) -> Option<usize> {
+        self.exact.get(name).copied()
+    }
+
+    /// Returns the entry whose name matches `name` ignoring case and slash direction.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("Objects\\Chair01.iff".to_string(), 0, vec![]),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use libfar::farlib;
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// let index = archive.index();
+    /// let chair = index.find("objects/chair01.IFF").expect("No such file");
+    /// println!("found {}", archive.file_list[chair].name);
+    /// ```
+    pub fn find(&self, name : &str) -> Option<usize> {
+        self.find_all(name).next()
+    }
+
+    /// Returns every entry whose name matches `name` ignoring case and slash direction, in
+    /// manifest order. Archives can hold several entries that only differ in case.
+    pub fn find_all(&self, name : &str) -> impl Iterator<Item = usize> + '_ {
+        let name = normalize(name);
+        let start = self.normalized.partition_point(|(key, _)| *key < name);
+        self.normalized[start..].iter()
+            .take_while(move |(key, _)| *key == name)
+            .map(|&(_, i)| i)
+    }
+
+    /// Returns every entry whose name starts with `prefix`, ignoring case and slash direction,
+    /// sorted by normalised name. The prefix is matched as a string, so use `"objects\\"`
+    /// rather than `"objects"` to list what's inside a directory.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("Textures\\Wall.bmp".to_string(), 0, vec![]),
+    /// #     libfar::farlib::FarFile::new_from_file("objects\\chair.iff".to_string(), 0, vec![]),
+    /// #     libfar::farlib::FarFile::new_from_file("textures\\floor.bmp".to_string(), 0, vec![]),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use libfar::farlib;
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// let index = archive.index();
+    /// for i in index.starting_with("textures/") {
+    ///     println!("{}", archive.file_list[i].name);
+    /// }
+    /// # assert_eq!(index.starting_with("textures/").collect::<Vec<_>>(), vec![2, 0]);
+    /// ```
+    pub fn starting_with(&self, prefix : &str) -> impl Iterator<Item = usize> + '_ {
+        let prefix = normalize(prefix);
+        let start = self.normalized.partition_point(|(key, _)| *key < prefix);
+        self.normalized[start..].iter()
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|&(_, i)| i)
+    }
+}
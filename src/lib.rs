@@ -1 +1,17 @@
-pub mod farlib;
\ No newline at end of file
+pub mod borrowed;
+pub mod conflicts;
+pub mod directory;
+pub mod encoding;
+pub mod extract;
+pub mod farlib;
+pub mod index;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod reader;
+pub mod refpack;
+pub mod salvage;
+mod sha256;
+pub mod tree;
+pub mod verify;
+pub mod vfs;
+pub mod writer;
\ No newline at end of file
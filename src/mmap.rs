@@ -0,0 +1,92 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::borrowed::{Entries, FarArchiveRef, FarEntryRef};
+use crate::farlib::{EntryId, FarError, Result};
+
+/// A FAR archive backed by a memory-mapped file.
+/// The manifest is parsed in place as a `FarArchiveRef`, so names and file data are handed out
+/// as slices borrowed from the map, and listing and reading files doesn't allocate or copy
+/// anything per entry (except when decompressing compressed files).
+///
+/// Requires the `mmap` feature.
+///
+/// # Examples
+/// ```no_run
+/// use libfar::mmap::MappedFarArchive;
+/// // safety: nothing else modifies test.far while it is mapped
+/// let archive = unsafe { MappedFarArchive::open("test.far") }.expect("Failed to open archive");
+/// for entry in archive.entries() {
+///     let entry = entry.expect("Invalid entry");
+///     println!("{} ({} bytes stored)", entry.name().unwrap_or("<invalid name>"), entry.data.len());
+/// }
+/// let data = archive.read("texture.bmp").expect("Failed to read file");
+/// ```
+pub struct MappedFarArchive {
+    map: Mmap,
+    /// The parsed header and manifest layout, with an empty buffer in place of the map.
+    archive: FarArchiveRef<'static>,
+}
+
+impl MappedFarArchive {
+    /// Maps the file at `path` into memory and checks its manifest.
+    ///
+    /// # Safety
+    /// The file must not be modified or truncated (by this or any other process) while it is
+    /// mapped, as that would change memory that the returned slices borrow.
+    pub unsafe fn open(path : impl AsRef<Path>) -> Result<MappedFarArchive> {
+        MappedFarArchive::from_file(&File::open(path)?)
+    }
+
+    /// Maps an already opened file into memory and checks its manifest.
+    ///
+    /// # Safety
+    /// See `MappedFarArchive::open`.
+    pub unsafe fn from_file(file : &File) -> Result<MappedFarArchive> {
+        let map = Mmap::map(file)?;
+        let archive = FarArchiveRef::parse(&map)?.with_buf(&[]);
+        Ok(MappedFarArchive {
+            map,
+            archive,
+        })
+    }
+
+    /// Returns the archive parsed in place, borrowing from the map.
+    pub fn archive(&self) -> FarArchiveRef<'_> {
+        self.archive.with_buf(&self.map)
+    }
+
+    /// Returns an iterator over the archive's files, in manifest order.
+    pub fn entries(&self) -> Entries<'_> {
+        self.archive().entries()
+    }
+
+    /// Returns the whole mapped archive.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Returns a file's bytes exactly as they are stored in the archive, borrowed from the map.
+    pub fn data<'a>(&self, id : impl Into<EntryId<'a>>) -> Result<&[u8]> {
+        Ok(self.entry(id.into())?.data)
+    }
+
+    /// Returns a file's contents, borrowed from the map if the file is stored uncompressed and
+    /// decompressed into a new buffer otherwise.
+    pub fn read<'a>(&self, id : impl Into<EntryId<'a>>) -> Result<Cow<'_, [u8]>> {
+        self.entry(id.into())?.contents()
+    }
+
+    fn entry(&self, id : EntryId) -> Result<FarEntryRef<'_>> {
+        let archive = self.archive();
+        match id {
+            EntryId::Name(name) => archive.get(name),
+            EntryId::Index(index) => archive.entries()
+                .nth(index)
+                .unwrap_or_else(|| Err(FarError::NotFound(id.to_string()))),
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::encoding::NameEncoding;
+use crate::extract::{self, ExtractOptions};
+use crate::farlib::{self, EntryId, FarError, FarFile, FarFileInfo, FarVariant, Result};
+use crate::index::NameIndex;
+use crate::tree::FarTree;
+
+/// Reader over a single file's data, returned by `reader_for`.
+/// Uncompressed files are streamed straight from the archive, while compressed files are
+/// decompressed into memory first.
+pub enum EntryReader<'s, S> {
+    Stored(io::Take<&'s mut S>),
+    Decompressed(io::Cursor<Vec<u8>>),
+}
+
+impl<S : Read> Read for EntryReader<'_, S> {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        match self {
+            EntryReader::Stored(reader) => reader.read(buf),
+            EntryReader::Decompressed(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Amount of the manifest read at first, doubled until the whole manifest has been read.
+const MANIFEST_CHUNK: u64 = 64 * 1024;
+
+/// Reads the header and manifest of the archive in `inner`, returning the version, manifest
+/// offset, variant and files. Only as much data after the manifest offset is read as parsing the
+/// manifest needs, so a bogus header or trailing data can't make this load the whole archive.
+pub(crate) fn read_manifest<R : Read + Seek>(inner : &mut R, names : NameEncoding) -> Result<(u32, u32, FarVariant, Vec<FarFileInfo>)> {
+    let len = inner.seek(SeekFrom::End(0))?;
+    inner.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::new();
+    inner.take(16).read_to_end(&mut header)?;
+    let (version, manifest_offset) = farlib::parse_header(&header)?;
+    let base = manifest_offset as u64;
+    let manifest_len = len.checked_sub(base).ok_or(FarError::ManifestOutOfBounds { offset: manifest_offset })?;
+    inner.seek(SeekFrom::Start(base))?;
+    let mut manifest = Vec::new();
+    let mut wanted = manifest_len.min(MANIFEST_CHUNK);
+    loop {
+        inner.take(wanted - manifest.len() as u64).read_to_end(&mut manifest)?;
+        if wanted == manifest_len || farlib::holds_manifest(&manifest, base, version) {
+            break;
+        }
+        wanted = manifest_len.min(wanted * 2);
+    }
+    let (variant, files) = farlib::parse_manifest(&manifest, base, version, names)?;
+    Ok((version, manifest_offset, variant, files))
+}
+
+/// Streaming reader for FAR archives.
+/// Only the header and manifest are read when the reader is created; file data is read on demand
+/// by seeking to each entry, so an archive can be opened straight from a `std::fs::File` without
+/// loading it into memory.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+/// use libfar::reader::FarReader;
+/// let file = File::open("test.far").expect("Failed to open file");
+/// let mut reader = FarReader::new(file).expect("Not a valid archive");
+/// for info in reader.files() {
+///     println!("{} ({} bytes)", info.name, info.uncompressed_size);
+/// }
+/// let first = reader.read_file(0).expect("Failed to read file");
+/// let texture = reader.read_file("texture.bmp").expect("Failed to read file");
+/// ```
+pub struct FarReader<R> {
+    inner: R,
+    version: u32,
+    variant: FarVariant,
+    files: Vec<FarFileInfo>,
+}
+
+impl<R: Read + Seek> FarReader<R> {
+    /// Creates a new FarReader, reading the header and manifest from `inner`.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use std::io::Cursor;
+    /// use libfar::reader::FarReader;
+    /// let reader = FarReader::new(Cursor::new(buffer)).expect("Not a valid archive");
+    /// println!("archive has {} files", reader.files().len());
+    /// ```
+    pub fn new(inner : R) -> Result<FarReader<R>> {
+        FarReader::with_encoding(inner, NameEncoding::default())
+    }
+
+    /// Like `new`, but decodes entry names with `names` instead of requiring them to be UTF-8.
+    pub fn with_encoding(mut inner : R, names : NameEncoding) -> Result<FarReader<R>> {
+        let (version, _, variant, files) = read_manifest(&mut inner, names)?;
+        Ok(FarReader {
+            inner,
+            version,
+            variant,
+            files,
+        })
+    }
+
+    /// Returns the version of the archive.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the layout of the archive's manifest, only meaningful for version 1 archives.
+    pub fn variant(&self) -> FarVariant {
+        self.variant
+    }
+
+    /// Returns information about every file in the archive, in manifest order.
+    pub fn files(&self) -> &[FarFileInfo] {
+        &self.files
+    }
+
+    /// Builds a `NameIndex` over the archive's files, as described in `FarArchive::index`.
+    pub fn index(&self) -> NameIndex<'_> {
+        NameIndex::new(&self.files)
+    }
+
+    /// Builds a `FarTree` over the archive's files, as described in `FarArchive::tree`.
+    pub fn tree(&self) -> FarTree<'_> {
+        FarTree::new(&self.files)
+    }
+
+    /// Returns the entries that share their data with an earlier entry, as described in
+    /// `FarArchive::aliases`.
+    pub fn aliases(&self) -> Vec<(usize, usize)> {
+        farlib::find_aliases(self.files.iter().map(|info| (info.offset, info.stored_size)))
+    }
+
+    /// Returns a reader over the data of a file. The file can be given either by name or by index.
+    /// Uncompressed files are streamed without reading them into memory, while compressed files
+    /// are decompressed into memory first.
+    pub fn reader_for<'a>(&mut self, id : impl Into<EntryId<'a>>) -> Result<EntryReader<'_, R>> {
+        let index = farlib::find_entry(&self.files, id.into())?;
+        open_entry_decoded(&mut self.inner, &self.files[index], index)
+    }
+
+    /// Returns a reader over the bytes of a file as they are stored in the archive, without
+    /// decompressing them.
+    pub fn raw_reader_for<'a>(&mut self, id : impl Into<EntryId<'a>>) -> Result<io::Take<&mut R>> {
+        let index = farlib::find_entry(&self.files, id.into())?;
+        open_entry(&mut self.inner, &self.files[index], index)
+    }
+
+    /// Reads a file into a FarFile struct, decompressing it if it is compressed.
+    /// The file can be given either by name or by index.
+    pub fn read_file<'a>(&mut self, id : impl Into<EntryId<'a>>) -> Result<FarFile> {
+        let index = farlib::find_entry(&self.files, id.into())?;
+        let data = read_entry(&mut self.inner, &self.files[index], index)?;
+        Ok(FarFile {
+            name: self.files[index].name.clone(),
+            size: data.len() as u32,
+            data,
+        })
+    }
+
+    /// Reads a file into a FarFile struct exactly as it is stored, without decompressing it.
+    pub fn read_file_raw<'a>(&mut self, id : impl Into<EntryId<'a>>) -> Result<FarFile> {
+        let index = farlib::find_entry(&self.files, id.into())?;
+        let mut data = Vec::new();
+        open_entry(&mut self.inner, &self.files[index], index)?.read_to_end(&mut data)?;
+        Ok(FarFile {
+            name: self.files[index].name.clone(),
+            size: data.len() as u32,
+            data,
+        })
+    }
+
+    /// Extracts every file to disk under `dest_dir`, decompressing compressed files and streaming
+    /// the rest. Works like `FarArchive::extract_all`, returning the paths that were written.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::fs::File;
+    /// use libfar::extract::ExtractOptions;
+    /// use libfar::reader::FarReader;
+    /// let file = File::open("test.far").expect("Failed to open file");
+    /// let mut reader = FarReader::new(file).expect("Not a valid archive");
+    /// reader.extract_all("out", &ExtractOptions::default()).expect("Failed to extract archive");
+    /// ```
+    pub fn extract_all(&mut self, dest_dir : impl AsRef<Path>, options : &ExtractOptions) -> Result<Vec<PathBuf>> {
+        let mut written = Vec::new();
+        for index in 0..self.files.len() {
+            written.extend(self.extract_to(index, dest_dir.as_ref(), options)?);
+        }
+        Ok(written)
+    }
+
+    /// Extracts a single file to disk under `dest_dir`, following the same rules as `extract_all`.
+    /// Returns the path that was written, or `None` if the file was skipped because it already
+    /// exists.
+    pub fn extract_to<'a>(&mut self, id : impl Into<EntryId<'a>>, dest_dir : impl AsRef<Path>, options : &ExtractOptions) -> Result<Option<PathBuf>> {
+        let index = farlib::find_entry(&self.files, id.into())?;
+        let info = &self.files[index];
+        extract::extract_entry(dest_dir.as_ref(), &info.name, options, |output| {
+            io::copy(&mut open_entry_decoded(&mut self.inner, info, index)?, output)?;
+            Ok(())
+        })
+    }
+
+    /// Consumes the FarReader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Seeks `source` to the start of an entry's data and limits it to the entry's stored size.
+/// `index` is only used for error reporting.
+pub(crate) fn open_entry<'s, S : Read + Seek>(source : &'s mut S, info : &FarFileInfo, index : usize) -> Result<io::Take<&'s mut S>> {
+    let len = source.seek(SeekFrom::End(0))?;
+    if info.offset as u64 + info.stored_size as u64 > len {
+        return Err(FarError::EntryOutOfBounds {
+            entry_index: Some(index as u32),
+            offset: info.offset,
+            size: info.stored_size,
+        });
+    }
+    source.seek(SeekFrom::Start(info.offset as u64))?;
+    Ok(source.take(info.stored_size as u64))
+}
+
+/// Reads an entry's data into memory, decompressing it if it is compressed.
+pub(crate) fn read_entry<S : Read + Seek>(source : &mut S, info : &FarFileInfo, index : usize) -> Result<Vec<u8>> {
+    let mut stored = Vec::new();
+    open_entry(source, info, index)?.read_to_end(&mut stored)?;
+    farlib::decode_entry(info, stored, Some(index as u32))
+}
+
+/// Like `open_entry`, but decompresses compressed entries into memory.
+pub(crate) fn open_entry_decoded<'s, S : Read + Seek>(source : &'s mut S, info : &FarFileInfo, index : usize) -> Result<EntryReader<'s, S>> {
+    if info.is_compressed() {
+        Ok(EntryReader::Decompressed(io::Cursor::new(read_entry(source, info, index)?)))
+    } else {
+        Ok(EntryReader::Stored(open_entry(source, info, index)?))
+    }
+}
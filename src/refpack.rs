@@ -0,0 +1,244 @@
+use crate::farlib::{FarError, Result};
+
+/// RefPack data is sometimes wrapped in a 13 byte "persist" header (a type byte followed by three
+/// u32 sizes), most notably in The Sims Online's version 3 archives.
+const PERSIST_HEADER_LEN: usize = 13;
+
+/// Caps how much memory is reserved up front based on the (untrusted) size in the header.
+const MAX_PREALLOCATION: usize = 1 << 24;
+
+/// Returns true if `data` starts with a RefPack header.
+pub fn is_refpack(data : &[u8]) -> bool {
+    data.len() >= 2 && data[0] & 0x3E == 0x10 && data[1] == 0xFB
+}
+
+/// Finds where the RefPack stream starts within a compressed entry, skipping the persist header
+/// if there is one.
+pub fn find_stream(data : &[u8]) -> Option<&[u8]> {
+    if is_refpack(data) {
+        Some(data)
+    } else if data.len() > PERSIST_HEADER_LEN && is_refpack(&data[PERSIST_HEADER_LEN..]) {
+        Some(&data[PERSIST_HEADER_LEN..])
+    } else {
+        None
+    }
+}
+
+/// Decompresses a RefPack (also known as QFS) stream.
+///
+/// # Examples
+/// ```
+/// use libfar::refpack;
+/// // header for 3 bytes of output, then a command writing 3 literal bytes and ending the stream
+/// let compressed = [0x10, 0xFB, 0x00, 0x00, 0x03, 0xFF, b'a', b'b', b'c'];
+/// assert_eq!(refpack::decompress(&compressed).unwrap(), b"abc");
+/// ```
+pub fn decompress(data : &[u8]) -> Result<Vec<u8>> {
+    let mut input = Input { data, pos: 0 };
+    let flags = input.byte()?;
+    if input.byte()? != 0xFB || flags & 0x3E != 0x10 {
+        return Err(invalid());
+    }
+    // sizes are big endian, 4 bytes wide if the high bit is set and 3 bytes otherwise
+    let size_len = if flags & 0x80 != 0 { 4 } else { 3 };
+    if flags & 0x01 != 0 {
+        // compressed size, which we don't need
+        input.take(size_len)?;
+    }
+    let size = input.take(size_len)?.iter().fold(0usize, |acc, &b| acc << 8 | b as usize);
+
+    let mut output = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+    loop {
+        let b0 = input.byte()? as usize;
+        let (literal, copy_len, copy_offset) = match b0 {
+            0x00..=0x7F => {
+                let b1 = input.byte()? as usize;
+                (b0 & 0x03, ((b0 & 0x1C) >> 2) + 3, ((b0 & 0x60) << 3) + b1 + 1)
+            }
+            0x80..=0xBF => {
+                let b1 = input.byte()? as usize;
+                let b2 = input.byte()? as usize;
+                (b1 >> 6, (b0 & 0x3F) + 4, ((b1 & 0x3F) << 8) + b2 + 1)
+            }
+            0xC0..=0xDF => {
+                let b1 = input.byte()? as usize;
+                let b2 = input.byte()? as usize;
+                let b3 = input.byte()? as usize;
+                (b0 & 0x03, ((b0 & 0x0C) << 6) + b3 + 5, ((b0 & 0x10) << 12) + (b1 << 8) + b2 + 1)
+            }
+            0xE0..=0xFB => (((b0 & 0x1F) << 2) + 4, 0, 0),
+            // 0xFC..=0xFF ends the stream, after up to 3 final literal bytes
+            _ => {
+                output.extend_from_slice(input.take(b0 & 0x03)?);
+                break;
+            }
+        };
+        output.extend_from_slice(input.take(literal)?);
+        if copy_len > 0 {
+            if copy_offset > output.len() {
+                return Err(invalid());
+            }
+            // copies can overlap the bytes they produce, so go one byte at a time
+            let start = output.len() - copy_offset;
+            for i in start..start + copy_len {
+                output.push(output[i]);
+            }
+        }
+        if output.len() > size {
+            return Err(invalid());
+        }
+    }
+    if output.len() != size {
+        return Err(invalid());
+    }
+    Ok(output)
+}
+
+fn invalid() -> FarError {
+    FarError::InvalidCompressedData { entry_index: None }
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, len : usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(invalid)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+/// Longest distance a copy command can reach back.
+const MAX_OFFSET: usize = 131_072;
+/// Longest copy a single command can encode.
+const MAX_COPY_LEN: usize = 1028;
+/// How many earlier positions with the same hash are tried when looking for a match.
+const MAX_CHAIN: usize = 128;
+
+/// Compresses `data` into a RefPack stream, including the header.
+///
+/// # Examples
+/// ```
+/// use libfar::refpack;
+/// let data = b"hello hello hello hello".to_vec();
+/// let compressed = refpack::compress(&data);
+/// assert_eq!(refpack::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data : &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() / 2 + 16);
+    // sizes are big endian, and need 4 bytes (flagged with 0x80) if they don't fit in 3
+    if data.len() > 0xFF_FFFF {
+        output.extend_from_slice(&[0x90, 0xFB]);
+        output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    } else {
+        output.extend_from_slice(&[0x10, 0xFB]);
+        output.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+    }
+
+    // hash chains over 3 byte sequences, used to find earlier occurrences of the current position
+    let mut head = vec![usize::MAX; 1 << 16];
+    let mut prev = vec![usize::MAX; data.len()];
+    let hash = |pos : usize| {
+        let h = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+        (h.wrapping_mul(2_654_435_761) >> 16) as usize
+    };
+    let insert = |pos : usize, head : &mut Vec<usize>, prev : &mut Vec<usize>| {
+        if pos + 3 <= data.len() {
+            let h = hash(pos);
+            prev[pos] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    let mut literal_start = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_offset = 0;
+        if pos + 3 <= data.len() {
+            let max_len = (data.len() - pos).min(MAX_COPY_LEN);
+            let mut candidate = head[hash(pos)];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= MAX_OFFSET && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len && is_encodable(len, pos - candidate) {
+                    best_len = len;
+                    best_offset = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+        if best_len == 0 {
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+            continue;
+        }
+        let literal = write_literals(&mut output, &data[literal_start..pos]);
+        write_copy(&mut output, literal, best_len, best_offset);
+        for i in pos..pos + best_len {
+            insert(i, &mut head, &mut prev);
+        }
+        pos += best_len;
+        literal_start = pos;
+    }
+    let literal = write_literals(&mut output, &data[literal_start..]);
+    output.push(0xFC | literal.len() as u8);
+    output.extend_from_slice(literal);
+    output
+}
+
+/// Returns true if a copy of `len` bytes from `offset` bytes back fits in one of the copy commands.
+fn is_encodable(len : usize, offset : usize) -> bool {
+    match len {
+        0..=2 => false,
+        3 => offset <= 1024,
+        4 => offset <= 16_384,
+        _ => offset <= MAX_OFFSET,
+    }
+}
+
+/// Writes all but the last 0-3 bytes of `literal` as literal commands, returning the bytes that
+/// are left over to be written along with the next command.
+fn write_literals<'a>(output : &mut Vec<u8>, mut literal : &'a [u8]) -> &'a [u8] {
+    while literal.len() > 3 {
+        // literal commands hold a multiple of 4 bytes, up to 112
+        let len = (literal.len() & !3).min(112);
+        output.push(0xE0 | ((len - 4) >> 2) as u8);
+        output.extend_from_slice(&literal[..len]);
+        literal = &literal[len..];
+    }
+    literal
+}
+
+fn write_copy(output : &mut Vec<u8>, literal : &[u8], len : usize, offset : usize) {
+    let plain = literal.len();
+    let offset = offset - 1;
+    if len <= 10 && offset < 1024 {
+        let len = len - 3;
+        output.push((((offset >> 3) & 0x60) | (len << 2) | plain) as u8);
+        output.push(offset as u8);
+    } else if len <= 67 && offset < 16_384 {
+        let len = len - 4;
+        output.push((0x80 | len) as u8);
+        output.push(((plain << 6) | (offset >> 8)) as u8);
+        output.push(offset as u8);
+    } else {
+        let len = len - 5;
+        output.push((0xC0 | ((offset >> 12) & 0x10) | ((len >> 6) & 0x0C) | plain) as u8);
+        output.push((offset >> 8) as u8);
+        output.push(offset as u8);
+        output.push(len as u8);
+    }
+    output.extend_from_slice(literal);
+}
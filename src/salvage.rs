@@ -0,0 +1,209 @@
+use crate::encoding::NameEncoding;
+use crate::farlib::{self, FarArchive, FarError, FarFileInfo, FarVariant, ManifestEntries, Result};
+
+/// Why an entry that is listed in the manifest couldn't be recovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossReason {
+    /// The entry's data runs past the end of the buffer, e.g. because the download was cut off.
+    OutOfBounds,
+    /// The entry's name isn't valid in the name encoding (UTF-8 unless `salvage_with` is used).
+    InvalidName,
+}
+
+/// A manifest entry that was read but couldn't be recovered.
+#[derive(Debug, Clone)]
+pub struct LostEntry {
+    pub entry_index: u32,
+    /// The entry's name, with invalid UTF-8 replaced.
+    pub name: String,
+    pub offset: u32,
+    pub stored_size: u32,
+    pub reason: LossReason,
+}
+
+/// What `salvage` couldn't recover.
+#[derive(Debug)]
+pub struct SalvageReport {
+    /// Number of entries the manifest says the archive has, or `None` if the manifest is missing
+    /// (so there's no telling how many files were lost).
+    pub expected_entries: Option<u32>,
+    /// Number of entries whose manifest records are damaged or missing, so not even their names
+    /// are known.
+    pub unreadable_entries: u32,
+    /// Entries whose manifest records were read, but whose data couldn't be recovered.
+    pub lost: Vec<LostEntry>,
+    /// The error that stopped the manifest from being read any further, if any.
+    pub error: Option<FarError>,
+}
+
+impl SalvageReport {
+    /// Returns true if every file in the archive was recovered.
+    pub fn is_complete(&self) -> bool {
+        self.expected_entries.is_some() && self.unreadable_entries == 0 && self.lost.is_empty()
+    }
+}
+
+/// Recovers what it can from a truncated or damaged archive.
+/// Where `farlib::test` fails at the first problem, this reads every intact manifest entry and
+/// keeps those whose data lies fully inside `buf`, returning them as a FarArchive struct along
+/// with a report of what was lost. Use `load_file_data` or `extract` on the result as usual.
+///
+/// Only fails if `buf` doesn't start with a valid header, as nothing can be recovered then.
+///
+/// # Examples
+/// ```
+/// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+/// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+/// #     libfar::farlib::FarFile::new_from_file("b.txt".to_string(), 5, b"world".to_vec()),
+/// # ]).to_vec();
+/// # let buffer = &buffer[..buffer.len() - 4];
+/// // buffer is a truncated .far file
+/// use libfar::salvage;
+/// let (archive, report) = salvage::salvage(&buffer).expect("Not a FAR archive");
+/// println!("recovered {} files", archive.file_list.len());
+/// for lost in &report.lost {
+///     println!("lost {} ({:?})", lost.name, lost.reason);
+/// }
+/// if let Some(expected) = report.expected_entries {
+///     println!("{} entries couldn't be read at all, out of {}", report.unreadable_entries, expected);
+/// }
+/// let archive = archive.load_file_data(&buffer).expect("Failed to load files");
+/// // the end of b.txt's manifest record was cut off
+/// assert_eq!(archive.file_data[0].data, b"hello");
+/// assert_eq!(archive.file_data.len(), 1);
+/// assert_eq!(report.expected_entries, Some(2));
+/// assert_eq!(report.unreadable_entries, 1);
+/// assert!(report.lost.is_empty());
+/// assert!(!report.is_complete());
+/// ```
+///
+/// Entries whose records are intact but whose data is missing are listed in `lost`, and an
+/// archive cut off before its manifest recovers nothing:
+/// ```
+/// use libfar::farlib::{FarArchive, FarFile};
+/// use libfar::salvage::{self, LossReason};
+/// let mut buffer = FarArchive::new_from_files(vec![
+///     FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+///     FarFile::new_from_file("b.txt".to_string(), 5, b"world".to_vec()),
+/// ]).to_vec();
+/// // point b.txt's data past the end of the archive
+/// let manifest_offset = u32::from_le_bytes(buffer[12..16].try_into().unwrap()) as usize;
+/// let b_offset = manifest_offset + 4 + (16 + "a.txt".len()) + 8;
+/// buffer[b_offset..b_offset + 4].copy_from_slice(&1000u32.to_le_bytes());
+/// let (archive, report) = salvage::salvage(&buffer).expect("Not a FAR archive");
+/// assert_eq!(archive.file_list.len(), 1);
+/// assert_eq!((report.expected_entries, report.unreadable_entries), (Some(2), 0));
+/// assert_eq!(report.lost.len(), 1);
+/// assert_eq!((report.lost[0].entry_index, report.lost[0].name.as_str()), (1, "b.txt"));
+/// assert_eq!(report.lost[0].reason, LossReason::OutOfBounds);
+///
+/// let (archive, report) = salvage::salvage(&buffer[..manifest_offset]).expect("Not a FAR archive");
+/// assert!(archive.file_list.is_empty());
+/// assert_eq!(report.expected_entries, None);
+/// assert!(!report.is_complete());
+/// ```
+pub fn salvage(buf : &[u8]) -> Result<(FarArchive, SalvageReport)> {
+    salvage_with(buf, NameEncoding::default())
+}
+
+/// Like `salvage`, but decodes entry names with `names` instead of requiring them to be UTF-8.
+///
+/// # Examples
+/// ```
+/// # let mut writer = libfar::writer::FarWriter::with_options(std::io::Cursor::new(Vec::new()), 1,
+/// #     libfar::writer::WriteOptions { names: libfar::encoding::NameEncoding::Windows1252, ..Default::default() }).unwrap();
+/// # writer.add_bytes("caf\u{e9}.iff", b"hello").unwrap();
+/// # let buffer = writer.finish().unwrap().into_inner();
+/// // buffer is a Vec<u8> containing the contents of a .far file from The Sims
+/// use libfar::encoding::NameEncoding;
+/// use libfar::salvage;
+/// let (archive, report) = salvage::salvage_with(&buffer, NameEncoding::Windows1252).expect("Not a FAR archive");
+/// assert_eq!(archive.file_list[0].name, "café.iff");
+/// assert!(report.is_complete());
+/// ```
+pub fn salvage_with(buf : &[u8], names : NameEncoding) -> Result<(FarArchive, SalvageReport)> {
+    let (version, manifest_offset) = farlib::parse_header(buf)?;
+    let manifest = buf.get(manifest_offset as usize..).unwrap_or(&[]);
+    let base = manifest_offset as u64;
+
+    // a damaged version 1 manifest may fit either layout, or neither, so try both and keep
+    // whichever recovers more files, falling back on the layout that would normally be detected.
+    // Reading a 1a manifest as 1b leaves the high bytes of each name length at the start of the
+    // name, so names with NUL bytes in them don't count.
+    let score = |files : &[FarFileInfo]| files.iter().filter(|info| !info.name.contains('\0')).count();
+    let detected = farlib::detect_variant(manifest, base, version).ok();
+    let variants = match version {
+        1 => vec![FarVariant::V1a, FarVariant::V1b],
+        _ => vec![FarVariant::default()],
+    };
+    let mut best: Option<(FarVariant, Vec<FarFileInfo>, SalvageReport)> = None;
+    for variant in variants {
+        let (files, report) = salvage_entries(buf, manifest_offset, version, variant, names);
+        let better = match &best {
+            None => true,
+            Some((_, best_files, _)) => {
+                score(&files) > score(best_files) || (score(&files) == score(best_files) && detected == Some(variant))
+            }
+        };
+        if better {
+            best = Some((variant, files, report));
+        }
+    }
+    let (variant, files, report) = best.expect("at least one variant was tried");
+    Ok((FarArchive {
+        version,
+        variant,
+        file_count: files.len() as u32,
+        file_list: files,
+        file_data: vec![],
+    }, report))
+}
+
+fn salvage_entries(buf : &[u8], manifest_offset : u32, version : u32, variant : FarVariant, names : NameEncoding) -> (Vec<FarFileInfo>, SalvageReport) {
+    let mut files = Vec::new();
+    let mut report = SalvageReport {
+        expected_entries: None,
+        unreadable_entries: 0,
+        lost: Vec::new(),
+        error: None,
+    };
+    let manifest = match buf.get(manifest_offset as usize..) {
+        Some(manifest) => manifest,
+        None => {
+            report.error = Some(FarError::ManifestOutOfBounds { offset: manifest_offset });
+            return (files, report);
+        }
+    };
+    let mut entries = match ManifestEntries::new(manifest, manifest_offset as u64, version, variant) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.error = Some(e);
+            return (files, report);
+        }
+    };
+    report.expected_entries = Some(entries.len());
+
+    for (i, entry) in entries.by_ref().enumerate() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.error = Some(e);
+                break;
+            }
+        };
+        let index = i as u32;
+        let in_bounds = entry.offset as u64 + entry.stored_size as u64 <= buf.len() as u64;
+        match entry.to_info(index, names) {
+            Ok(info) if in_bounds => files.push(info),
+            result => report.lost.push(LostEntry {
+                entry_index: index,
+                name: String::from_utf8_lossy(entry.name).into_owned(),
+                offset: entry.offset,
+                stored_size: entry.stored_size,
+                reason: if result.is_ok() { LossReason::OutOfBounds } else { LossReason::InvalidName },
+            }),
+        }
+    }
+    report.unreadable_entries = entries.len() - (files.len() + report.lost.len()) as u32;
+    (files, report)
+}
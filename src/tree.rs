@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use crate::farlib::{FarError, FarFileInfo, Result};
+use crate::index::normalize;
+
+/// Directory view over an archive's flat entry names, built by `FarArchive::tree` or
+/// `FarReader::tree`. Names are split into directories at both backslashes and forward slashes,
+/// so `objects\chairs\chair01.iff` is a file in `objects\chairs`, which is in `objects`.
+///
+/// Paths are compared the way the games compare names, ignoring case and slash direction, and
+/// empty components (from doubled or leading separators) are skipped. `""` is the root.
+/// Directories are spelled the way the first entry inside them spells them. If a name is both
+/// a file and a directory, both show up in `read_dir`, and `metadata` returns the file.
+///
+/// # Examples
+/// ```
+/// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+/// #     libfar::farlib::FarFile::new_from_file("objects\\chairs\\chair01.iff".to_string(), 0, vec![]),
+/// #     libfar::farlib::FarFile::new_from_file("objects\\table.iff".to_string(), 0, vec![]),
+/// #     libfar::farlib::FarFile::new_from_file("readme.txt".to_string(), 0, vec![]),
+/// # ]).to_vec();
+/// // buffer is a Vec<u8> containing the contents of a .far file
+/// use libfar::farlib;
+/// let archive = farlib::test(&buffer).expect("Not a valid archive");
+/// let tree = archive.tree();
+/// for entry in tree.read_dir("objects").expect("No such directory") {
+///     println!("{}{}", entry.name(), if entry.is_dir() { "\\" } else { "" });
+/// }
+/// assert!(tree.is_dir("Objects/Chairs"));
+/// let size = tree.metadata("objects/table.iff").expect("No such file").len();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FarTree<'a> {
+    files: &'a [FarFileInfo],
+    /// Children of each directory, sorted by normalised name. The root is directory 0.
+    dirs: Vec<Vec<DirEntry<'a>>>,
+    /// Directories by normalised path.
+    dir_ids: HashMap<String, usize>,
+    /// Files by normalised path, keeping the first entry if several have the same path.
+    file_ids: HashMap<String, usize>,
+}
+
+/// A file or directory listed by `FarTree::read_dir` or `FarTree::walk`.
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntry<'a> {
+    name: &'a str,
+    path: &'a str,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    File(usize),
+    Dir(usize),
+}
+
+impl<'a> DirEntry<'a> {
+    /// Returns the last component of the entry's path.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns the entry's path as spelled in the archive, without a trailing separator.
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// Returns true if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, Kind::Dir(_))
+    }
+
+    /// Returns true if the entry is a file.
+    pub fn is_file(&self) -> bool {
+        matches!(self.kind, Kind::File(_))
+    }
+
+    /// Returns the position of the file in the archive's file list, or `None` for directories.
+    pub fn file_index(&self) -> Option<usize> {
+        match self.kind {
+            Kind::File(index) => Some(index),
+            Kind::Dir(_) => None,
+        }
+    }
+}
+
+/// Information about a path in a `FarTree`, returned by `FarTree::metadata`.
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata<'a> {
+    file: Option<(usize, &'a FarFileInfo)>,
+}
+
+impl<'a> Metadata<'a> {
+    /// Returns true if the path is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file.is_none()
+    }
+
+    /// Returns true if the path is a file.
+    pub fn is_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Returns the size of the file after decompression, or 0 for directories.
+    pub fn len(&self) -> u64 {
+        self.file.map_or(0, |(_, info)| info.uncompressed_size as u64)
+    }
+
+    /// Returns true if the path is a directory or an empty file.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the position of the file in the archive's file list, or `None` for directories.
+    pub fn file_index(&self) -> Option<usize> {
+        self.file.map(|(index, _)| index)
+    }
+
+    /// Returns the file's manifest entry, or `None` for directories.
+    pub fn info(&self) -> Option<&'a FarFileInfo> {
+        self.file.map(|(_, info)| info)
+    }
+}
+
+impl<'a> FarTree<'a> {
+    /// Builds a tree over `files`. Entries whose names are empty or only separators are left out.
+    pub fn new(files : &'a [FarFileInfo]) -> FarTree<'a> {
+        let mut tree = FarTree {
+            files,
+            dirs: vec![Vec::new()],
+            dir_ids: HashMap::from([(String::new(), 0)]),
+            file_ids: HashMap::new(),
+        };
+        for (i, info) in files.iter().enumerate() {
+            let name = info.name.as_str();
+            let mut components = components(name).peekable();
+            let (mut parent, mut key) = (0, String::new());
+            while let Some((start, component)) = components.next() {
+                let path = &name[..start + component.len()];
+                if !key.is_empty() {
+                    key.push('\\');
+                }
+                key.push_str(&normalize(component));
+                if components.peek().is_none() {
+                    if !tree.file_ids.contains_key(&key) {
+                        tree.file_ids.insert(key.clone(), i);
+                        tree.dirs[parent].push(DirEntry { name: component, path, kind: Kind::File(i) });
+                    }
+                    break;
+                }
+                parent = match tree.dir_ids.get(&key) {
+                    Some(&dir) => dir,
+                    None => {
+                        let dir = tree.dirs.len();
+                        tree.dirs.push(Vec::new());
+                        tree.dir_ids.insert(key.clone(), dir);
+                        tree.dirs[parent].push(DirEntry { name: component, path, kind: Kind::Dir(dir) });
+                        dir
+                    }
+                };
+            }
+        }
+        for children in &mut tree.dirs {
+            children.sort_by_cached_key(|entry| (normalize(entry.name), entry.is_file()));
+        }
+        tree
+    }
+
+    /// Returns the files and directories directly inside the directory at `path`, sorted by
+    /// name. Returns `FarError::NotFound` if there is no such directory.
+    pub fn read_dir(&self, path : &str) -> Result<&[DirEntry<'a>]> {
+        match self.dir_ids.get(&key(path)) {
+            Some(&dir) => Ok(&self.dirs[dir]),
+            None => Err(FarError::NotFound(path.to_string())),
+        }
+    }
+
+    /// Returns information about the file or directory at `path`.
+    /// Returns `FarError::NotFound` if there is no such file or directory.
+    pub fn metadata(&self, path : &str) -> Result<Metadata<'a>> {
+        let key = key(path);
+        if let Some(&index) = self.file_ids.get(&key) {
+            Ok(Metadata { file: Some((index, &self.files[index])) })
+        } else if self.dir_ids.contains_key(&key) {
+            Ok(Metadata { file: None })
+        } else {
+            Err(FarError::NotFound(path.to_string()))
+        }
+    }
+
+    /// Returns true if `path` is a directory.
+    pub fn is_dir(&self, path : &str) -> bool {
+        self.dir_ids.contains_key(&key(path))
+    }
+
+    /// Returns true if `path` is a file.
+    pub fn is_file(&self, path : &str) -> bool {
+        self.file_ids.contains_key(&key(path))
+    }
+
+    /// Returns every file and directory in the tree, depth first, with each directory listed
+    /// before what's inside it and siblings sorted by name.
+    pub fn walk(&self) -> Walk<'_, 'a> {
+        Walk {
+            tree: self,
+            stack: vec![self.dirs[0].iter()],
+        }
+    }
+}
+
+/// Iterator over a whole `FarTree`, returned by `FarTree::walk`.
+#[derive(Debug, Clone)]
+pub struct Walk<'t, 'a> {
+    tree: &'t FarTree<'a>,
+    stack: Vec<std::slice::Iter<'t, DirEntry<'a>>>,
+}
+
+impl<'a> Iterator for Walk<'_, 'a> {
+    type Item = DirEntry<'a>;
+
+    fn next(&mut self) -> Option<DirEntry<'a>> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(entry) => {
+                    if let Kind::Dir(dir) = entry.kind {
+                        self.stack.push(self.tree.dirs[dir].iter());
+                    }
+                    return Some(*entry);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Splits a name into its non-empty components, along with where each one starts.
+fn components(name : &str) -> impl Iterator<Item = (usize, &str)> {
+    name.split(['\\', '/'])
+        .scan(0, |start, component| {
+            let item = (*start, component);
+            *start += component.len() + 1;
+            Some(item)
+        })
+        .filter(|(_, component)| !component.is_empty())
+}
+
+/// Normalises a path for looking it up in the tree.
+fn key(path : &str) -> String {
+    components(path).map(|(_, component)| normalize(component)).collect::<Vec<_>>().join("\\")
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::encoding::NameEncoding;
+use crate::farlib::{self, FarError, FarVariant, ManifestEntries, RawEntry};
+use crate::refpack;
+
+/// A single problem found by `verify`. Offsets are byte offsets into the archive, and entry
+/// indices are positions in the manifest.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Problem {
+    /// The header or manifest couldn't be parsed (any further), so entries after this point
+    /// weren't checked.
+    Unreadable(FarError),
+    /// An entry's data runs past the end of the archive.
+    OutOfBounds { entry_index: u32, offset: u32, size: u32 },
+    /// An entry's data overlaps the 16 byte header.
+    OverlapsHeader { entry_index: u32, offset: u32, size: u32 },
+    /// An entry's data overlaps the manifest.
+    OverlapsManifest { entry_index: u32, offset: u32, size: u32 },
+    /// Two entries' data overlap, for `len` bytes starting at `offset`.
+    /// Entries that share exactly the same data are aliases rather than overlaps.
+    Overlap { entry_index: u32, other_index: u32, offset: u32, len: u32 },
+    /// An entry has the same name as an earlier entry, so it can't be found by name.
+    DuplicateName { entry_index: u32, first_index: u32, name: String },
+    /// An entry's name is empty.
+    EmptyName { entry_index: u32 },
+    /// An entry's name can't be decoded (it isn't valid UTF-8, unless another encoding was
+    /// given to `verify_with`).
+    InvalidName { entry_index: u32 },
+    /// An entry's two size fields differ (marking it as compressed), but its data isn't
+    /// compressed, or decompresses to a different size than the manifest says.
+    SizeMismatch { entry_index: u32, uncompressed_size: u32, stored_size: u32 },
+    /// An entry is compressed, but its data isn't a valid RefPack stream.
+    InvalidCompressedData { entry_index: u32 },
+    /// There are bytes after the end of the manifest.
+    TrailingData { offset: u64, len: u64 },
+    /// A range of bytes between the header and the manifest isn't part of any entry, and isn't
+    /// padding to align the next entry.
+    Gap { offset: u64, len: u64 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unreadable(e) => write!(f, "{}", e),
+            Problem::OutOfBounds { entry_index, offset, size } => {
+                write!(f, "entry {} ({} bytes at offset {}) runs past the end of the archive", entry_index, size, offset)
+            }
+            Problem::OverlapsHeader { entry_index, offset, size } => {
+                write!(f, "entry {} ({} bytes at offset {}) overlaps the header", entry_index, size, offset)
+            }
+            Problem::OverlapsManifest { entry_index, offset, size } => {
+                write!(f, "entry {} ({} bytes at offset {}) overlaps the manifest", entry_index, size, offset)
+            }
+            Problem::Overlap { entry_index, other_index, offset, len } => {
+                write!(f, "entry {} overlaps entry {} for {} bytes at offset {}", entry_index, other_index, len, offset)
+            }
+            Problem::DuplicateName { entry_index, first_index, name } => {
+                write!(f, "entry {} has the same name as entry {} (\"{}\")", entry_index, first_index, name)
+            }
+            Problem::EmptyName { entry_index } => write!(f, "entry {} has an empty name", entry_index),
+            Problem::InvalidName { entry_index } => write!(f, "entry {} has a name that can't be decoded", entry_index),
+            Problem::SizeMismatch { entry_index, uncompressed_size, stored_size } => {
+                write!(f, "entry {} has mismatched sizes ({} bytes uncompressed, {} bytes stored)", entry_index, uncompressed_size, stored_size)
+            }
+            Problem::InvalidCompressedData { entry_index } => write!(f, "entry {} has invalid compressed data", entry_index),
+            Problem::TrailingData { offset, len } => write!(f, "{} bytes of trailing data after the manifest at offset {}", len, offset),
+            Problem::Gap { offset, len } => write!(f, "{} unreferenced bytes at offset {}", len, offset),
+        }
+    }
+}
+
+/// The result of `verify`: what could be read of the archive, and every problem found in it.
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// The archive's version, if the header could be read.
+    pub version: Option<u32>,
+    /// The layout of the manifest, if it could be read (only meaningful for version 1).
+    pub variant: Option<FarVariant>,
+    /// Number of manifest entries that could be read and checked.
+    pub entries: u32,
+    /// Entries sharing their data with an earlier entry, as (entry, earlier entry) pairs.
+    /// These are written on purpose when deduplicating, so they aren't problems.
+    pub aliases: Vec<(u32, u32)>,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks an archive for structural problems, listing every problem found rather than stopping
+/// at the first one like `farlib::test` does.
+/// Compressed entries are decompressed to check that their data and sizes are valid.
+///
+/// # Examples
+/// ```
+/// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+/// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+/// # ]).to_vec();
+/// // buffer is a Vec<u8> containing the contents of a .far file
+/// use libfar::verify;
+/// let report = verify::verify(&buffer);
+/// for problem in &report.problems {
+///     println!("{}", problem);
+/// }
+/// assert!(report.is_ok());
+/// ```
+///
+/// Each problem is reported with where it was found:
+/// ```
+/// use libfar::verify::{self, Problem};
+/// // builds a version 1 archive holding `data`, with one file per (offset, size)
+/// fn archive(data : &[u8], files : &[(u32, u32)]) -> Vec<u8> {
+///     let mut buffer = b"FAR!byAZ".to_vec();
+///     buffer.extend_from_slice(&1u32.to_le_bytes());
+///     buffer.extend_from_slice(&(16 + data.len() as u32).to_le_bytes());
+///     buffer.extend_from_slice(data);
+///     buffer.extend_from_slice(&(files.len() as u32).to_le_bytes());
+///     for (i, &(offset, size)) in files.iter().enumerate() {
+///         let name = format!("{}.txt", i);
+///         for field in [size, size, offset, name.len() as u32] {
+///             buffer.extend_from_slice(&field.to_le_bytes());
+///         }
+///         buffer.extend_from_slice(name.as_bytes());
+///     }
+///     buffer
+/// }
+///
+/// let report = verify::verify(&archive(b"aaaabbbb", &[(16, 6), (20, 4)]));
+/// assert!(matches!(report.problems[..], [Problem::Overlap { entry_index: 1, other_index: 0, offset: 20, len: 2 }]));
+///
+/// let report = verify::verify(&archive(b"aaaa", &[(16, 4), (20, 100)]));
+/// // running past the end, the entry also overlaps the manifest
+/// assert!(report.problems.iter().any(|problem| matches!(problem, Problem::OutOfBounds { entry_index: 1, offset: 20, size: 100 })));
+///
+/// let report = verify::verify(&archive(b"aaaa", &[(8, 4), (16, 4)]));
+/// assert!(matches!(report.problems[..], [Problem::OverlapsHeader { entry_index: 0, offset: 8, size: 4 }]));
+///
+/// let report = verify::verify(&archive(b"aaaa", &[(16, 8)]));
+/// assert!(matches!(report.problems[..], [Problem::OverlapsManifest { entry_index: 0, offset: 16, size: 8 }]));
+///
+/// let report = verify::verify(&archive(b"aaaaxxxxbbbb", &[(16, 4), (24, 4)]));
+/// assert!(matches!(report.problems[..], [Problem::Gap { offset: 20, len: 4 }]));
+///
+/// let mut buffer = archive(b"aaaa", &[(16, 4)]);
+/// let manifest_end = buffer.len() as u64;
+/// buffer.extend_from_slice(b"junk");
+/// let report = verify::verify(&buffer);
+/// assert!(matches!(report.problems[..], [Problem::TrailingData { offset, len: 4 }] if offset == manifest_end));
+/// assert_eq!(report.entries, 1);
+/// ```
+pub fn verify(buf : &[u8]) -> VerifyReport {
+    verify_with(buf, NameEncoding::default())
+}
+
+/// Like `verify`, but checks entry names against `names` instead of requiring them to be UTF-8.
+pub fn verify_with(buf : &[u8], names : NameEncoding) -> VerifyReport {
+    let mut report = VerifyReport {
+        version: None,
+        variant: None,
+        entries: 0,
+        aliases: Vec::new(),
+        problems: Vec::new(),
+    };
+    let (version, manifest_offset) = match farlib::parse_header(buf) {
+        Ok(header) => header,
+        Err(e) => {
+            report.problems.push(Problem::Unreadable(e));
+            return report;
+        }
+    };
+    report.version = Some(version);
+    let manifest = match buf.get(manifest_offset as usize..) {
+        Some(manifest) => manifest,
+        None => {
+            report.problems.push(Problem::Unreadable(FarError::ManifestOutOfBounds { offset: manifest_offset }));
+            return report;
+        }
+    };
+    let base = manifest_offset as u64;
+    let entries = farlib::detect_variant(manifest, base, version)
+        .and_then(|variant| Ok((variant, ManifestEntries::new(manifest, base, version, variant)?)));
+    let (variant, mut entries) = match entries {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.problems.push(Problem::Unreadable(e));
+            return report;
+        }
+    };
+    report.variant = Some(variant);
+
+    let mut parsed = Vec::new();
+    for entry in entries.by_ref() {
+        match entry {
+            Ok(entry) => parsed.push(entry),
+            Err(e) => report.problems.push(Problem::Unreadable(e)),
+        }
+    }
+    report.entries = parsed.len() as u32;
+    let manifest_end = base + entries.consumed() as u64;
+
+    let mut seen = HashMap::new();
+    for (i, entry) in parsed.iter().enumerate() {
+        check_name(i as u32, entry, names, &mut seen, &mut report.problems);
+        check_entry(buf, i as u32, entry, base, manifest_end, &mut report.problems);
+    }
+    report.aliases = farlib::find_aliases(parsed.iter().map(|entry| (entry.offset, entry.stored_size)))
+        .into_iter()
+        .map(|(entry, original)| (entry as u32, original as u32))
+        .collect();
+    check_layout(buf, &parsed, base, &mut report.problems);
+    if manifest_end < buf.len() as u64 {
+        report.problems.push(Problem::TrailingData {
+            offset: manifest_end,
+            len: buf.len() as u64 - manifest_end,
+        });
+    }
+    report
+}
+
+/// Checks that an entry's name can be decoded, and that no earlier entry has the same name.
+fn check_name<'a>(index : u32, entry : &RawEntry<'a>, names : NameEncoding, seen : &mut HashMap<&'a [u8], u32>, problems : &mut Vec<Problem>) {
+    if entry.name.is_empty() {
+        problems.push(Problem::EmptyName { entry_index: index });
+    } else if names.decode(entry.name).is_none() {
+        problems.push(Problem::InvalidName { entry_index: index });
+    }
+    if let Some(&first_index) = seen.get(entry.name) {
+        problems.push(Problem::DuplicateName {
+            entry_index: index,
+            first_index,
+            name: String::from_utf8_lossy(entry.name).into_owned(),
+        });
+    } else {
+        seen.insert(entry.name, index);
+    }
+}
+
+/// Checks a single entry's bounds and data.
+fn check_entry(
+    buf : &[u8],
+    index : u32,
+    entry : &RawEntry,
+    manifest_offset : u64,
+    manifest_end : u64,
+    problems : &mut Vec<Problem>,
+) {
+    let (offset, size) = (entry.offset, entry.stored_size);
+    let start = offset as u64;
+    let end = start + size as u64;
+    if size > 0 && start < farlib::HEADER_LEN as u64 {
+        problems.push(Problem::OverlapsHeader { entry_index: index, offset, size });
+    }
+    if size > 0 && start < manifest_end && end > manifest_offset {
+        problems.push(Problem::OverlapsManifest { entry_index: index, offset, size });
+    }
+    let data = match buf.get(start as usize..end as usize) {
+        Some(data) => data,
+        None => {
+            problems.push(Problem::OutOfBounds { entry_index: index, offset, size });
+            return;
+        }
+    };
+
+    if farlib::is_compressed(entry.uncompressed_size, entry.stored_size, entry.v3) {
+        let mismatch = Problem::SizeMismatch {
+            entry_index: index,
+            uncompressed_size: entry.uncompressed_size,
+            stored_size: entry.stored_size,
+        };
+        match refpack::find_stream(data).map(refpack::decompress) {
+            None => problems.push(mismatch),
+            Some(Err(_)) => problems.push(Problem::InvalidCompressedData { entry_index: index }),
+            Some(Ok(data)) if data.len() != entry.uncompressed_size as usize => problems.push(mismatch),
+            Some(Ok(_)) => {}
+        }
+    }
+}
+
+/// Checks how entries' data is laid out between the header and the manifest, finding overlaps
+/// between entries and bytes that no entry uses. Aliased entries only count once, and zero
+/// padding written to align files (see `WriteOptions::alignment`) doesn't count as a gap.
+fn check_layout(buf : &[u8], entries : &[RawEntry], manifest_offset : u64, problems : &mut Vec<Problem>) {
+    let mut ranges: Vec<(u64, u64, u32)> = entries.iter().enumerate()
+        .filter(|(_, entry)| entry.stored_size > 0)
+        .map(|(i, entry)| (entry.offset as u64, entry.offset as u64 + entry.stored_size as u64, i as u32))
+        .collect();
+    ranges.sort();
+    // aliases sort next to each other, with the earliest entry first
+    ranges.dedup_by_key(|&mut (start, end, _)| (start, end));
+
+    // the entry reaching furthest so far, which any later entry starting before it overlaps
+    let mut furthest: Option<(u64, u32)> = None;
+    let mut covered = farlib::HEADER_LEN as u64;
+    for &(start, end, index) in &ranges {
+        if let Some((furthest_end, other_index)) = furthest {
+            if start < furthest_end {
+                problems.push(Problem::Overlap {
+                    entry_index: index,
+                    other_index,
+                    offset: start as u32,
+                    len: (end.min(furthest_end) - start) as u32,
+                });
+            }
+        }
+        if furthest.is_none_or(|(furthest_end, _)| end > furthest_end) {
+            furthest = Some((end, index));
+        }
+        if start > covered && covered < manifest_offset {
+            check_gap(buf, covered, start.min(manifest_offset), problems);
+        }
+        covered = covered.max(end);
+    }
+    if covered < manifest_offset {
+        check_gap(buf, covered, manifest_offset, problems);
+    }
+}
+
+/// Reports the bytes from `start` to `end` as a gap, unless they are alignment padding: zeros
+/// leading up to an offset that is aligned to a larger power of two than the gap is long.
+fn check_gap(buf : &[u8], start : u64, end : u64, problems : &mut Vec<Problem>) {
+    let alignment = 1u64 << end.trailing_zeros().min(63);
+    let zeroed = buf.get(start as usize..end as usize).is_some_and(|gap| gap.iter().all(|&b| b == 0));
+    if !zeroed || end - start >= alignment {
+        problems.push(Problem::Gap {
+            offset: start,
+            len: end - start,
+        });
+    }
+}
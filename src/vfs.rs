@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::directory::{self, DirectoryOptions};
+use crate::farlib::{FarError, Result};
+use crate::index::normalize;
+use crate::reader::{EntryReader, FarReader};
+
+/// Layered view over several FAR archives and loose directories, resolving each name to the
+/// source that wins it, the way the games let expansion packs override the base game.
+///
+/// Every source is mounted with a priority. A name resolves to the highest priority source that
+/// has it, and between sources with the same priority, to the one mounted last. Names are
+/// compared the way the games compare them, ignoring case and slash direction.
+///
+/// # Examples
+/// ```no_run
+/// use std::io::Read;
+/// use libfar::vfs::FarVfs;
+/// let mut vfs = FarVfs::new();
+/// vfs.mount_archive("GameData/Objects.far", 0).expect("Failed to mount archive");
+/// vfs.mount_archive("ExpansionShared/Objects.far", 1).expect("Failed to mount archive");
+/// vfs.mount_directory("Downloads", 2).expect("Failed to mount directory");
+/// let entry = vfs.resolve("objects/chair01.iff").expect("No such file");
+/// println!("chair01.iff comes from {}", entry.source.display());
+/// let mut data = Vec::new();
+/// vfs.open("objects/chair01.iff").expect("Failed to open file")
+///     .read_to_end(&mut data).expect("Failed to read file");
+/// ```
+#[derive(Default)]
+pub struct FarVfs {
+    mounts: Vec<Mount>,
+    /// Mount ids, highest priority (and, among equals, most recently mounted) first.
+    order: Vec<usize>,
+}
+
+struct Mount {
+    path: PathBuf,
+    priority: i32,
+    source: Source,
+    /// Entries by normalised name, keeping the first entry if several have the same name.
+    names: HashMap<String, usize>,
+}
+
+enum Source {
+    Archive(FarReader<File>),
+    /// Files as walked by `directory::walk`, as pairs of name and path on disk.
+    Directory(Vec<(String, PathBuf)>),
+}
+
+/// Where a name in a `FarVfs` resolves to, returned by `FarVfs::resolve`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsEntry<'a> {
+    /// Id of the source, as returned when it was mounted.
+    pub mount: usize,
+    /// Path of the archive or directory the file comes from.
+    pub source: &'a Path,
+    /// The file's name as spelled in its source.
+    pub name: &'a str,
+    /// True if the source is a loose directory rather than an archive.
+    pub is_loose: bool,
+}
+
+/// Reader over a file in a `FarVfs`, returned by `FarVfs::open`.
+pub enum VfsReader<'a> {
+    Archive(EntryReader<'a, File>),
+    Loose(File),
+}
+
+impl Read for VfsReader<'_> {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        match self {
+            VfsReader::Archive(reader) => reader.read(buf),
+            VfsReader::Loose(file) => file.read(buf),
+        }
+    }
+}
+
+impl FarVfs {
+    /// Creates an empty FarVfs.
+    pub fn new() -> FarVfs {
+        FarVfs::default()
+    }
+
+    /// Opens the archive at `path` and mounts it with `priority`, returning its mount id.
+    pub fn mount_archive(&mut self, path : impl AsRef<Path>, priority : i32) -> Result<usize> {
+        let path = path.as_ref();
+        let reader = FarReader::new(File::open(path)?)?;
+        Ok(self.mount_reader(path, reader, priority))
+    }
+
+    /// Mounts an already opened archive with `priority`, returning its mount id.
+    /// `path` is only used to report where files come from, so this is the way to mount
+    /// archives opened with `FarReader::with_encoding`.
+    pub fn mount_reader(&mut self, path : impl Into<PathBuf>, reader : FarReader<File>, priority : i32) -> usize {
+        let names = names(reader.files().iter().map(|info| info.name.as_str()));
+        self.mount(path.into(), priority, Source::Archive(reader), names)
+    }
+
+    /// Mounts the files in the directory at `path` and its subdirectories with `priority`,
+    /// returning its mount id. The directory is walked once, when it is mounted, so files added
+    /// to it later won't be found.
+    pub fn mount_directory(&mut self, path : impl AsRef<Path>, priority : i32) -> Result<usize> {
+        let path = path.as_ref();
+        let files = directory::walk(path, &DirectoryOptions::default())?;
+        let names = names(files.iter().map(|(name, _)| name.as_str()));
+        Ok(self.mount(path.to_path_buf(), priority, Source::Directory(files), names))
+    }
+
+    fn mount(&mut self, path : PathBuf, priority : i32, source : Source, names : HashMap<String, usize>) -> usize {
+        let id = self.mounts.len();
+        self.mounts.push(Mount { path, priority, source, names });
+        // the sort is stable, so pushing to the front puts newer mounts before older ones
+        self.order.insert(0, id);
+        self.order.sort_by_key(|&id| std::cmp::Reverse(self.mounts[id].priority));
+        id
+    }
+
+    /// Returns where `name` resolves to, or `None` if no source has it.
+    pub fn resolve(&self, name : &str) -> Option<VfsEntry<'_>> {
+        self.resolve_all(name).next()
+    }
+
+    /// Returns every source that has `name`, starting with the one it resolves to and followed
+    /// by the ones it overrides.
+    pub fn resolve_all(&self, name : &str) -> impl Iterator<Item = VfsEntry<'_>> {
+        let name = normalize(name);
+        self.order.iter().filter_map(move |&id| {
+            self.mounts[id].names.get(&name).map(|&index| self.entry(id, index))
+        })
+    }
+
+    /// Returns every file in the vfs as it resolves, sorted by normalised name.
+    pub fn entries(&self) -> Vec<VfsEntry<'_>> {
+        let mut entries = HashMap::new();
+        for &id in self.order.iter().rev() {
+            for (name, &index) in &self.mounts[id].names {
+                entries.insert(name.as_str(), self.entry(id, index));
+            }
+        }
+        let mut entries: Vec<(&str, VfsEntry)> = entries.into_iter().collect();
+        entries.sort_unstable_by_key(|&(name, _)| name);
+        entries.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    /// Returns the path of the archive or directory mounted as `mount`.
+    pub fn mount_path(&self, mount : usize) -> Option<&Path> {
+        self.mounts.get(mount).map(|mount| mount.path.as_path())
+    }
+
+    /// Returns a reader over the data of the file `name` resolves to, decompressing it if it
+    /// is compressed. Returns `FarError::NotFound` if no source has it.
+    pub fn open(&mut self, name : &str) -> Result<VfsReader<'_>> {
+        let key = normalize(name);
+        let found = self.order.iter().find_map(|&id| self.mounts[id].names.get(&key).map(|&index| (id, index)));
+        let (id, index) = found.ok_or_else(|| FarError::NotFound(name.to_string()))?;
+        match &mut self.mounts[id].source {
+            Source::Archive(reader) => Ok(VfsReader::Archive(reader.reader_for(index)?)),
+            Source::Directory(files) => Ok(VfsReader::Loose(File::open(&files[index].1)?)),
+        }
+    }
+
+    /// Reads the whole file `name` resolves to into memory.
+    pub fn read(&mut self, name : &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open(name)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn entry(&self, id : usize, index : usize) -> VfsEntry<'_> {
+        let mount = &self.mounts[id];
+        let (name, is_loose) = match &mount.source {
+            Source::Archive(reader) => (reader.files()[index].name.as_str(), false),
+            Source::Directory(files) => (files[index].0.as_str(), true),
+        };
+        VfsEntry {
+            mount: id,
+            source: &mount.path,
+            name,
+            is_loose,
+        }
+    }
+}
+
+fn names<'a>(names : impl Iterator<Item = &'a str>) -> HashMap<String, usize> {
+    let mut map = HashMap::new();
+    for (i, name) in names.enumerate() {
+        map.entry(normalize(name)).or_insert(i);
+    }
+    map
+}
@@ -0,0 +1,506 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::encoding::NameEncoding;
+use crate::farlib::{FarError, FarFile, FarFileInfo, FarV3Info, FarVariant, Result};
+use crate::reader;
+use crate::refpack;
+use crate::sha256::sha256;
+
+/// Decides which files are RefPack-compressed when writing an archive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    /// Store every file uncompressed.
+    #[default]
+    Never,
+    /// Compress every file.
+    Always,
+    /// Compress every file, but only keep the compressed data if it is smaller.
+    IfSmaller,
+    /// Compress files with one of these extensions (e.g. `"iff"`), compared case-insensitively.
+    ByExtension(Vec<String>),
+}
+
+impl CompressionPolicy {
+    fn applies_to(&self, name : &str) -> bool {
+        match self {
+            CompressionPolicy::Never => false,
+            CompressionPolicy::Always | CompressionPolicy::IfSmaller => true,
+            CompressionPolicy::ByExtension(extensions) => {
+                let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or("");
+                extensions.iter().any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+            }
+        }
+    }
+}
+
+/// Options controlling how archives are written.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    pub compression: CompressionPolicy,
+    /// Manifest layout to use for version 1 archives.
+    pub variant: FarVariant,
+    /// Store the data of byte-identical files once, pointing all of their manifest entries at
+    /// the same offset. Files are read into memory one at a time to be hashed with SHA-256.
+    /// When appending to an archive, files are only compared with other appended files.
+    pub dedup: bool,
+    /// Start every file's data at a multiple of this many bytes (e.g. 2048 to align files to
+    /// disc sectors), padding the gaps with zeros. 0 and 1 both mean files are packed tightly.
+    /// Must be 0 or a power of two, as `verify` only recognises padding before such offsets;
+    /// creating a FarWriter with any other alignment fails with `FarError::InvalidOptions`.
+    pub alignment: u32,
+    /// How names are encoded in the manifest. Names of files read from an archive are written
+    /// back exactly as they were stored (see `FarFileInfo::raw_name`).
+    pub names: NameEncoding,
+}
+
+/// Streaming writer for FAR archives.
+/// File data is copied straight to the underlying writer as files are added, and only the
+/// manifest is kept in memory, so archives of any size can be packed with constant memory.
+/// Files that are compressed (see `WriteOptions`) have to be read into memory one at a time.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+/// use libfar::writer::FarWriter;
+/// let output = File::create("test.far").expect("Failed to create file");
+/// let mut writer = FarWriter::new(output).expect("Failed to write header");
+/// let texture = File::open("texture.bmp").expect("Failed to open file");
+/// writer.add_file("texture.bmp", texture).expect("Failed to add file");
+/// writer.add_bytes("readme.txt", b"hello").expect("Failed to add file");
+/// writer.finish().expect("Failed to write manifest");
+/// ```
+pub struct FarWriter<W> {
+    inner: W,
+    version: u32,
+    options: WriteOptions,
+    bytes_written: u64,
+    files: Vec<FarFileInfo>,
+    /// Offsets of data already written, by length and SHA-256 digest, used when deduplicating.
+    written: HashMap<(usize, [u8; 32]), u32>,
+}
+
+impl<W : Write> FarWriter<W> {
+    /// Creates a new FarWriter for a version 1 archive, writing the archive header to `inner`.
+    /// The manifest offset in the header is left as zero until the archive is finished.
+    pub fn new(inner : W) -> Result<FarWriter<W>> {
+        FarWriter::with_version(inner, 1)
+    }
+
+    /// Creates a new FarWriter for an archive of the given version (1 or 3).
+    pub fn with_version(inner : W, version : u32) -> Result<FarWriter<W>> {
+        FarWriter::with_options(inner, version, WriteOptions::default())
+    }
+
+    /// Creates a new FarWriter for an archive of the given version (1 or 3), written according
+    /// to `options`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::io::Cursor;
+    /// use libfar::farlib::FarError;
+    /// use libfar::writer::{CompressionPolicy, FarWriter, WriteOptions};
+    /// let options = WriteOptions {
+    ///     compression: CompressionPolicy::ByExtension(vec!["iff".to_string()]),
+    ///     ..Default::default()
+    /// };
+    /// let mut writer = FarWriter::with_options(Cursor::new(Vec::new()), 1, options)
+    ///     .expect("Failed to write header");
+    /// writer.add_bytes("chair.iff", &[0; 64]).expect("Failed to add file");
+    /// assert!(writer.files()[0].is_compressed());
+    ///
+    /// let options = WriteOptions { alignment: 3, ..Default::default() };
+    /// let result = FarWriter::with_options(Cursor::new(Vec::new()), 1, options);
+    /// assert!(matches!(result, Err(FarError::InvalidOptions(_))));
+    /// ```
+    pub fn with_options(mut inner : W, version : u32, options : WriteOptions) -> Result<FarWriter<W>> {
+        if version != 1 && version != 3 {
+            return Err(FarError::UnsupportedVersion(version));
+        }
+        check_options(&options)?;
+        inner.write_all(b"FAR!byAZ")?;
+        inner.write_all(&version.to_le_bytes())?;
+        // wait to write manifest offset until calculated later
+        inner.write_all(&[0; 4])?;
+        Ok(FarWriter {
+            inner,
+            version,
+            options,
+            bytes_written: 16,
+            files: Vec::new(),
+            written: HashMap::new(),
+        })
+    }
+
+    /// Copies everything from `data` into the archive as a new file.
+    pub fn add_file<R : Read>(&mut self, name : impl Into<String>, data : R) -> Result<()> {
+        self.add_entry(name.into(), None, data, FarV3Info::default())
+    }
+
+    /// Copies everything from `data` into the archive as a new file, with the given version 3
+    /// manifest fields. The fields are ignored when writing a version 1 archive, and the
+    /// compression fields are overwritten to match how the data is stored.
+    pub fn add_file_v3<R : Read>(&mut self, name : impl Into<String>, data : R, v3 : FarV3Info) -> Result<()> {
+        self.add_entry(name.into(), None, data, v3)
+    }
+
+    /// Adds a file, storing `raw_name` in the manifest in place of `name` if it is given.
+    pub(crate) fn add_entry<R : Read>(&mut self, name : String, raw_name : Option<Vec<u8>>, data : R, mut v3 : FarV3Info) -> Result<()> {
+        let (offset, size, stored_size) = if self.options.compression.applies_to(&name) {
+            let uncompressed = read_file(data)?;
+            let compressed = refpack::compress(&uncompressed);
+            // the reader can only tell a file is compressed if its two sizes differ
+            let keep = compressed.len() != uncompressed.len()
+                && (self.options.compression != CompressionPolicy::IfSmaller || compressed.len() < uncompressed.len());
+            let stored = if keep { &compressed } else { &uncompressed };
+            (self.write_stored(stored)?, uncompressed.len() as u64, stored.len() as u64)
+        } else if self.options.dedup {
+            let stored = read_file(data)?;
+            (self.write_stored(&stored)?, stored.len() as u64, stored.len() as u64)
+        } else {
+            let offset = self.align()?;
+            let size = self.copy_data(data)?;
+            (offset, size, size)
+        };
+        // make sure the data didn't run past what a u32 can address
+        self.offset()?;
+        let size = u32::try_from(size).map_err(|_| FarError::ArchiveTooLarge)?;
+        let compressed = size as u64 != stored_size;
+        v3.data_type = if compressed { 0x80 } else { 0 };
+        v3.compressed = compressed as u8;
+        self.files.push(FarFileInfo {
+            name,
+            uncompressed_size: size,
+            stored_size: stored_size as u32,
+            offset,
+            v3: if self.version == 3 { Some(v3) } else { None },
+            raw_name,
+        });
+        Ok(())
+    }
+
+    /// Writes a file's stored bytes, returning the offset they were written at. When
+    /// deduplicating, returns the offset of an identical earlier file instead of writing them.
+    fn write_stored(&mut self, stored : &[u8]) -> Result<u32> {
+        let key = self.options.dedup.then(|| (stored.len(), sha256(stored)));
+        if let Some(&offset) = key.as_ref().and_then(|key| self.written.get(key)) {
+            return Ok(offset);
+        }
+        let offset = self.align()?;
+        self.inner.write_all(stored)?;
+        self.bytes_written += stored.len() as u64;
+        if let Some(key) = key.filter(|_| !stored.is_empty()) {
+            self.written.insert(key, offset);
+        }
+        Ok(offset)
+    }
+
+    /// Copies a file's bytes into the archive exactly as they were stored in another archive,
+    /// keeping both sizes and the version 3 fields from `info`. This allows files to be re-packed
+    /// without decompressing and recompressing them, and without changing their manifest entries.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 5, b"hello".to_vec()),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use std::io::Cursor;
+    /// use libfar::farlib;
+    /// use libfar::writer::FarWriter;
+    /// let archive = farlib::test(&buffer).expect("Not a valid archive");
+    /// let mut source = Cursor::new(&buffer);
+    /// let mut writer = FarWriter::new(Cursor::new(Vec::new())).expect("Failed to write header");
+    /// for info in &archive.file_list {
+    ///     let stored = archive.raw_reader_for(&info.name, &mut source).expect("Failed to find file");
+    ///     writer.add_file_raw(info, stored).expect("Failed to add file");
+    /// }
+    /// let repacked = writer.finish().expect("Failed to write manifest").into_inner();
+    /// ```
+    pub fn add_file_raw<R : Read>(&mut self, info : &FarFileInfo, data : R) -> Result<()> {
+        let (offset, stored_size) = if self.options.dedup {
+            let stored = read_file(data)?;
+            (self.write_stored(&stored)?, stored.len() as u64)
+        } else {
+            let offset = self.align()?;
+            (offset, self.copy_data(data)?)
+        };
+        // make sure the data didn't run past what a u32 can address
+        self.offset()?;
+        self.files.push(FarFileInfo {
+            name: info.name.clone(),
+            uncompressed_size: info.uncompressed_size,
+            stored_size: stored_size as u32,
+            offset,
+            v3: if self.version == 3 { Some(info.v3.unwrap_or_default()) } else { None },
+            raw_name: info.raw_name.clone(),
+        });
+        Ok(())
+    }
+
+    /// Adds a new file to the archive from a buffer.
+    pub fn add_bytes(&mut self, name : impl Into<String>, data : &[u8]) -> Result<()> {
+        self.add_file(name, data)
+    }
+
+    /// Returns information about the files added so far.
+    pub fn files(&self) -> &[FarFileInfo] {
+        &self.files
+    }
+
+    /// Writes the manifest and returns the underlying writer along with the manifest offset.
+    ///
+    /// As the writer can't seek back to the header, the manifest offset in the header is left as
+    /// zero, and must be written as a little-endian u32 at byte 12 of the archive by the caller.
+    /// Use `finish` instead if the writer implements `Seek`.
+    ///
+    /// # Examples
+    /// ```
+    /// use libfar::farlib::{self, FarError};
+    /// use libfar::writer::FarWriter;
+    /// let mut writer = FarWriter::new(Vec::new()).expect("Failed to write header");
+    /// writer.add_bytes("a.txt", b"hello").expect("Failed to add file");
+    /// let (mut buffer, manifest_offset) = writer.finish_unpatched().expect("Failed to write manifest");
+    /// // until the manifest offset is patched in, the archive can't be read
+    /// assert!(matches!(farlib::test(&buffer), Err(FarError::ManifestOutOfBounds { offset: 0 })));
+    /// buffer[12..16].copy_from_slice(&manifest_offset.to_le_bytes());
+    /// assert_eq!(farlib::test(&buffer).expect("Not a valid archive").file_list[0].name, "a.txt");
+    /// ```
+    pub fn finish_unpatched(mut self) -> Result<(W, u32)> {
+        let manifest_offset = self.write_manifest()?;
+        Ok((self.inner, manifest_offset))
+    }
+
+    fn write_manifest(&mut self) -> Result<u32> {
+        let manifest_offset = self.offset()?;
+        let mut manifest = Vec::new();
+        // write file count
+        manifest.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for (i, file) in self.files.iter().enumerate() {
+            match self.version {
+                3 => write_entry_v3(&mut manifest, file, i as u32, self.options.names)?,
+                _ => write_entry_v1(&mut manifest, file, i as u32, self.options.variant, self.options.names)?,
+            }
+        }
+        self.inner.write_all(&manifest)?;
+        self.bytes_written += manifest.len() as u64;
+        self.inner.flush()?;
+        Ok(manifest_offset)
+    }
+
+    /// Pads the archive with zeros up to the next multiple of `options.alignment`, returning
+    /// the offset the next file's data will be written at.
+    fn align(&mut self) -> Result<u32> {
+        if self.options.alignment > 1 {
+            let padding = self.bytes_written.next_multiple_of(self.options.alignment as u64) - self.bytes_written;
+            io::copy(&mut io::repeat(0).take(padding), &mut self.inner)?;
+            self.bytes_written += padding;
+        }
+        self.offset()
+    }
+
+    fn offset(&self) -> Result<u32> {
+        u32::try_from(self.bytes_written).map_err(|_| FarError::ArchiveTooLarge)
+    }
+
+    /// Copies `data` into the archive, failing as soon as it runs past what a u32 can address
+    /// rather than reading the rest of it.
+    fn copy_data<R : Read>(&mut self, data : R) -> Result<u64> {
+        let limit = u32::MAX as u64 + 1 - self.bytes_written;
+        let size = io::copy(&mut data.take(limit), &mut self.inner)?;
+        self.bytes_written += size;
+        if size == limit {
+            return Err(FarError::ArchiveTooLarge);
+        }
+        Ok(size)
+    }
+}
+
+/// Reads a file into memory, failing as soon as it is too large for its size to fit in a u32.
+fn read_file<R : Read>(data : R) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    data.take(u32::MAX as u64 + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > u32::MAX as u64 {
+        return Err(FarError::ArchiveTooLarge);
+    }
+    Ok(buf)
+}
+
+impl<W : Write + Seek> FarWriter<W> {
+    /// Writes the manifest, then seeks back to fill in the manifest offset in the header.
+    /// Returns the underlying writer, positioned at the end of the archive.
+    pub fn finish(mut self) -> Result<W> {
+        let manifest_offset = self.write_manifest()?;
+        let bytes_written = self.bytes_written as i64;
+        // seek relative to the end, as the archive may not start at the beginning of `inner`
+        self.inner.seek(SeekFrom::Current(12 - bytes_written))?;
+        self.inner.write_all(&manifest_offset.to_le_bytes())?;
+        self.inner.seek(SeekFrom::Current(bytes_written - 16))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W : Read + Write + Seek> FarWriter<W> {
+    /// Opens the existing archive at the start of `inner` for appending.
+    /// The archive's files are kept as they are, and files added to the returned writer are
+    /// written over the old manifest, followed by a new manifest listing both old and new files
+    /// when the writer is finished. Nothing before the old manifest is rewritten, so this is
+    /// fast even for very large archives.
+    ///
+    /// The archive's version and manifest variant are kept, overriding `options.variant`.
+    /// If writing fails part way through, the old manifest may already have been overwritten,
+    /// so keep a backup of archives that can't be rebuilt.
+    ///
+    /// # Examples
+    /// ```
+    /// # let buffer = libfar::farlib::FarArchive::new_from_files(vec![
+    /// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 3, b"old".to_vec()),
+    /// # ]).to_vec();
+    /// // buffer is a Vec<u8> containing the contents of a .far file
+    /// use std::io::Cursor;
+    /// use libfar::writer::{FarWriter, WriteOptions};
+    /// let mut writer = FarWriter::append(Cursor::new(buffer.clone()), WriteOptions::default())
+    ///     .expect("Not a valid archive");
+    /// writer.add_bytes("patch.txt", b"hello").expect("Failed to add file");
+    /// let patched = writer.finish().expect("Failed to write manifest").into_inner();
+    /// # use libfar::farlib::{self, FarArchive, FarFile, FarVariant};
+    /// # let manifest_offset = u32::from_le_bytes(buffer[12..16].try_into().unwrap()) as usize;
+    /// // everything before the old manifest is left as it was
+    /// assert_eq!(patched[16..manifest_offset], buffer[16..manifest_offset]);
+    /// let archive = farlib::test(&patched).expect("Not a valid archive")
+    ///     .load_file_data(&patched).expect("Failed to load files");
+    /// assert_eq!(archive.file_data[0].data, b"old");
+    /// assert_eq!(archive.file_data[1].name, "patch.txt");
+    /// assert_eq!(archive.file_data[1].data, b"hello");
+    ///
+    /// // the archive's version and manifest layout are kept
+    /// for (version, variant) in [(1, FarVariant::V1b), (3, FarVariant::default())] {
+    ///     let mut archive = FarArchive::new_from_files(vec![FarFile::new_from_file("a.txt".to_string(), 3, b"old".to_vec())]);
+    ///     archive.version = version;
+    ///     archive.variant = variant;
+    ///     let mut writer = FarWriter::append(Cursor::new(archive.to_vec()), WriteOptions::default())
+    ///         .expect("Not a valid archive");
+    ///     writer.add_bytes("patch.txt", b"hello").expect("Failed to add file");
+    ///     let patched = writer.finish().expect("Failed to write manifest").into_inner();
+    ///     let archive = farlib::test(&patched).expect("Not a valid archive");
+    ///     assert_eq!((archive.version, archive.variant, archive.file_list.len()), (version, variant, 2));
+    /// }
+    /// ```
+    pub fn append(mut inner : W, options : WriteOptions) -> Result<FarWriter<W>> {
+        check_options(&options)?;
+        let (version, manifest_offset, variant, files) = reader::read_manifest(&mut inner, options.names)?;
+        // new data goes where the manifest is now, so nothing may be stored there
+        for (i, file) in files.iter().enumerate() {
+            if file.offset as u64 + file.stored_size as u64 > manifest_offset as u64 {
+                return Err(FarError::EntryOutOfBounds {
+                    entry_index: Some(i as u32),
+                    offset: file.offset,
+                    size: file.stored_size,
+                });
+            }
+        }
+        inner.seek(SeekFrom::Start(manifest_offset as u64))?;
+        Ok(FarWriter {
+            inner,
+            version,
+            options: WriteOptions { variant, ..options },
+            bytes_written: manifest_offset as u64,
+            files,
+            written: HashMap::new(),
+        })
+    }
+}
+
+/// Appends files to the archive at `path` in place, as described in `FarWriter::append`.
+/// Files are stored uncompressed; use `FarWriter::append` directly for more control.
+///
+/// # Examples
+/// ```
+/// # let path = std::env::temp_dir().join(format!("libfar-append-to-{}.far", std::process::id()));
+/// # std::fs::write(&path, libfar::farlib::FarArchive::new_from_files(vec![
+/// #     libfar::farlib::FarFile::new_from_file("a.txt".to_string(), 3, b"old".to_vec()),
+/// # ]).to_vec()).unwrap();
+/// // path is the path of a .far file
+/// use libfar::farlib::{self, FarFile};
+/// use libfar::writer;
+/// let patch = FarFile::new_from_file("patch.txt".to_string(), 5, b"hello".to_vec());
+/// writer::append_to(&path, vec![patch]).expect("Failed to append to archive");
+/// let buffer = std::fs::read(&path).expect("Failed to read file");
+/// let archive = farlib::test(&buffer).expect("Not a valid archive")
+///     .load_file_data(&buffer).expect("Failed to load files");
+/// assert_eq!(archive.file_data.len(), 2);
+/// assert_eq!(archive.file_data[1].data, b"hello");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn append_to(path : impl AsRef<Path>, files : impl IntoIterator<Item = FarFile>) -> Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut writer = FarWriter::append(file, WriteOptions::default())?;
+    for file in files {
+        writer.add_bytes(file.name, &file.data)?;
+    }
+    let mut file = writer.finish()?;
+    // drop anything left over past the new manifest
+    let len = file.stream_position()?;
+    file.set_len(len)?;
+    Ok(())
+}
+
+/// Checks options that would otherwise produce an archive `verify` complains about.
+fn check_options(options : &WriteOptions) -> Result<()> {
+    if options.alignment > 1 && !options.alignment.is_power_of_two() {
+        return Err(FarError::InvalidOptions(format!("alignment {} is not a power of two", options.alignment)));
+    }
+    Ok(())
+}
+
+/// Returns the bytes to store as a file's name: its original name if it has one, or its name
+/// encoded with `names` otherwise.
+fn name_bytes<'a>(file : &'a FarFileInfo, index : u32, names : NameEncoding) -> Result<Cow<'a, [u8]>> {
+    match &file.raw_name {
+        Some(raw_name) => Ok(Cow::Borrowed(raw_name)),
+        None => names.encode(&file.name).ok_or(FarError::InvalidName { entry_index: index }),
+    }
+}
+
+fn write_entry_v1(manifest : &mut Vec<u8>, file : &FarFileInfo, index : u32, variant : FarVariant, names : NameEncoding) -> Result<()> {
+    let name = name_bytes(file, index, names)?;
+    // write (size, stored size, offset, u32 (1a) or u16 (1b) name length, name)
+    manifest.extend_from_slice(&file.uncompressed_size.to_le_bytes());
+    manifest.extend_from_slice(&file.stored_size.to_le_bytes());
+    manifest.extend_from_slice(&file.offset.to_le_bytes());
+    match variant {
+        FarVariant::V1a => manifest.extend_from_slice(&(name.len() as u32).to_le_bytes()),
+        FarVariant::V1b => {
+            let name_len = u16::try_from(name.len()).map_err(|_| FarError::InvalidName { entry_index: index })?;
+            manifest.extend_from_slice(&name_len.to_le_bytes());
+        }
+    }
+    manifest.extend_from_slice(&name);
+    Ok(())
+}
+
+fn write_entry_v3(manifest : &mut Vec<u8>, file : &FarFileInfo, index : u32, names : NameEncoding) -> Result<()> {
+    // write (size, u24 stored size, data type, offset, compression flag, access number,
+    // u16 name length, type id, file id, name)
+    if file.stored_size > 0xFF_FFFF {
+        return Err(FarError::ArchiveTooLarge);
+    }
+    let name = name_bytes(file, index, names)?;
+    let name_len = u16::try_from(name.len()).map_err(|_| FarError::InvalidName { entry_index: index })?;
+    let v3 = file.v3.unwrap_or_default();
+    manifest.extend_from_slice(&file.uncompressed_size.to_le_bytes());
+    manifest.extend_from_slice(&file.stored_size.to_le_bytes()[..3]);
+    manifest.push(v3.data_type);
+    manifest.extend_from_slice(&file.offset.to_le_bytes());
+    manifest.push(v3.compressed);
+    manifest.push(v3.access_number);
+    manifest.extend_from_slice(&name_len.to_le_bytes());
+    manifest.extend_from_slice(&v3.type_id.to_le_bytes());
+    manifest.extend_from_slice(&v3.file_id.to_le_bytes());
+    manifest.extend_from_slice(&name);
+    Ok(())
+}